@@ -0,0 +1,123 @@
+// Files attached to the next chat message with /image and /file
+use crate::modules::{ImageSource, InputContentBlock, MessageContent};
+use base64::Engine;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The API rejects larger images
+pub const MAX_IMAGE_BYTES: u64 = 5 * 1024 * 1024;
+
+/// Default cut-off for text files, about 25k tokens
+pub const DEFAULT_FILE_LIMIT: usize = 100_000;
+
+/// A text file's contents wrapped in a block labeled with its path
+pub struct TextAttachment {
+    pub block: String,
+    pub truncated: bool,
+    pub size: usize,
+}
+
+// Identify the format from the file's leading bytes rather than trusting its extension
+fn image_media_type(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some("image/jpeg")
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+/// Read an image into a base64 content block, checking its type and size first
+pub fn load_image(path: &Path) -> io::Result<InputContentBlock> {
+    let size = fs::metadata(path)?.len();
+    if size > MAX_IMAGE_BYTES {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{:.1} MB is over the {} MB limit for images",
+                size as f64 / (1024.0 * 1024.0),
+                MAX_IMAGE_BYTES / (1024 * 1024)
+            ),
+        ));
+    }
+    let bytes = fs::read(path)?;
+    let media_type = image_media_type(&bytes).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "not a PNG, JPEG, GIF or WebP image",
+        )
+    })?;
+    Ok(InputContentBlock::Image {
+        source: ImageSource {
+            source_type: "base64".to_string(),
+            media_type: media_type.to_string(),
+            data: base64::engine::general_purpose::STANDARD.encode(&bytes),
+        },
+        cache_control: None,
+    })
+}
+
+/// Put attached images ahead of the message text, as the API recommends
+pub fn with_images(content: MessageContent, images: Vec<InputContentBlock>) -> MessageContent {
+    if images.is_empty() {
+        return content;
+    }
+    let mut blocks = images;
+    match content {
+        MessageContent::Text(text) => blocks.push(InputContentBlock::text(text)),
+        MessageContent::Blocks(existing) => blocks.extend(existing),
+    }
+    MessageContent::Blocks(blocks)
+}
+
+/// Read a text file for /file, keeping at most `limit` bytes of it
+pub fn load_text_file(path: &Path, limit: usize) -> io::Result<TextAttachment> {
+    let bytes = fs::read(path)?;
+    let size = bytes.len();
+    let mut text = String::from_utf8(bytes).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a UTF-8 text file (use /image for images)",
+        )
+    })?;
+    let truncated = size > limit;
+    if truncated {
+        let mut end = limit;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        text.push_str("\n[... truncated ...]");
+    }
+    let block = format!(
+        "<file path=\"{}\">\n{}\n</file>",
+        path.display(),
+        text.trim_end_matches('\n')
+    );
+    Ok(TextAttachment {
+        block,
+        truncated,
+        size,
+    })
+}
+
+/// Put attached files ahead of the message text, so the question comes after the context
+pub fn with_files(content: MessageContent, files: Vec<String>) -> MessageContent {
+    if files.is_empty() {
+        return content;
+    }
+    let context = files.join("\n\n");
+    match content {
+        MessageContent::Text(text) => MessageContent::Text(format!("{}\n\n{}", context, text)),
+        MessageContent::Blocks(mut blocks) => {
+            blocks.insert(0, InputContentBlock::text(context));
+            MessageContent::Blocks(blocks)
+        }
+    }
+}
@@ -0,0 +1,78 @@
+// Local cache of replies for `--cache`, one JSON file per request in `cache/` next to the
+// config. Entries are keyed by a hash of the whole request, so a different model, message or
+// parameter is simply a miss.
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// What is kept of a reply; usage is left out since a cached reply costs nothing
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    pub text: String,
+    pub model: String,
+    pub stop_reason: Option<String>,
+    pub stop_sequence: Option<String>,
+}
+
+pub struct ResponseCache {
+    dir: PathBuf,
+    // Also cache replies sampled above temperature 0
+    force: bool,
+}
+
+impl ResponseCache {
+    pub fn new(dir: PathBuf, force: bool) -> Self {
+        ResponseCache { dir, force }
+    }
+
+    /// Only replies that would come out the same anyway are reused, unless forced. The API
+    /// defaults to temperature 1, so an unset temperature counts as random.
+    pub fn applies(&self, temperature: Option<f32>) -> bool {
+        self.force || temperature == Some(0.0)
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.json", key))
+    }
+
+    /// A missing or unreadable entry is a miss
+    pub fn get(&self, key: u64) -> Option<Entry> {
+        let text = fs::read_to_string(self.path(key)).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    pub fn put(&self, key: u64, entry: &Entry) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.path(key), serde_json::to_string(entry)?)
+    }
+}
+
+/// FNV-1a over the request as JSON. Stable across runs and builds, unlike std's hasher.
+pub fn key<T: Serialize>(request: &T) -> u64 {
+    let json = serde_json::to_vec(request).unwrap_or_default();
+    json.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Delete every entry, returning how many there were
+pub fn clear(dir: &std::path::Path) -> io::Result<usize> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut removed = 0;
+    for entry in entries {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            fs::remove_file(path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
@@ -0,0 +1,1002 @@
+//! A client for the Messages API, for programs that want to talk to Claude without the rest
+//! of the command line tool. Replies are parsed, streamed and retried by the same code the
+//! command line tool uses, so the two behave alike.
+use crate::modules::{
+    parse_sse_line, AppError, ClaudeApiError, ClaudeApiRequest, ClaudeApiResponse, ClaudeMessage,
+    InputContentBlock, MessageContent, Metadata, ModelEntry, ModelList, StreamEvent, Thinking,
+    Tool,
+};
+use crate::throttle::Throttle;
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_BASE_URL: &str = "https://api.anthropic.com";
+pub const DEFAULT_API_VERSION: &str = "2023-06-01";
+// visit here to verify which model was called https://console.anthropic.com/settings/logs
+// sonnet is claude-3-5-sonnet-20241022
+pub const DEFAULT_MODEL: &str = "claude-3-5-haiku-20241022";
+pub const DEFAULT_MAX_TOKENS: u32 = 1024;
+/// How many times a rate-limited, overloaded or failing request is tried again
+pub const MAX_RETRIES: u32 = 3;
+/// The longest wait before a retry, whatever Retry-After asks for
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+/// How many times a response that is neither a reply nor an API error is asked for again
+pub const DEFAULT_MALFORMED_RETRIES: u32 = 1;
+
+/// What every request from a [`Client`] is sent with
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub model: String,
+    pub max_tokens: u32,
+    pub system: Option<String>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub stop_sequences: Vec<String>,
+    /// Tools Claude may ask to run; their calls come back in [`Reply::tool_calls`]
+    pub tools: Vec<Tool>,
+    /// Token budget for extended thinking, added on top of `max_tokens`
+    pub thinking: Option<u32>,
+    /// Sent as metadata.user_id: an opaque id of the person behind the request, so a shared
+    /// key's abuse detection and per-user limits see individual users
+    pub user_id: Option<String>,
+    /// Prompt caching breakpoints after the system prompt and after the first turn
+    pub cache_system: bool,
+    pub cache_first_turn: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            model: DEFAULT_MODEL.to_string(),
+            max_tokens: DEFAULT_MAX_TOKENS,
+            system: None,
+            temperature: None,
+            top_p: None,
+            stop_sequences: Vec::new(),
+            tools: Vec::new(),
+            thinking: None,
+            user_id: None,
+            cache_system: false,
+            cache_first_turn: false,
+        }
+    }
+}
+
+/// A tool Claude asked to run before it can go on
+#[derive(Clone, Debug)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub input: serde_json::Value,
+}
+
+impl ToolCall {
+    /// The block that records this call in the assistant's turn
+    pub fn to_block(&self) -> InputContentBlock {
+        InputContentBlock::ToolUse {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            input: self.input.clone(),
+        }
+    }
+}
+
+/// A finished reply and what it cost
+#[derive(Clone, Debug, Default)]
+pub struct Reply {
+    /// Every text block of the reply, in order
+    pub text: String,
+    /// The model that answered, which may be more specific than the one asked for
+    pub model: String,
+    /// Why the reply ended: `end_turn`, `max_tokens`, `stop_sequence` or `tool_use`
+    pub stop_reason: Option<String>,
+    /// The stop sequence that ended the reply, if one did
+    pub stop_sequence: Option<String>,
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+    /// Prompt caching: input tokens written to and served from the cache
+    pub cache_write_tokens: u32,
+    pub cache_read_tokens: u32,
+    /// Tools Claude asked to run; the reply then stops with `tool_use`
+    pub tool_calls: Vec<ToolCall>,
+    /// Thinking and redacted thinking blocks, which must go back along with tool results
+    pub thinking: Vec<InputContentBlock>,
+    /// The request-id header, which Anthropic support asks for
+    pub request_id: Option<String>,
+    /// How long the exchange took, retries included, and when streaming, until the first text
+    pub elapsed: Duration,
+    pub first_token: Option<Duration>,
+}
+
+impl Reply {
+    /// The reply as it goes into history, with any tool calls after its text
+    pub fn content(&self) -> MessageContent {
+        if self.tool_calls.is_empty() {
+            return self.text.clone().into();
+        }
+        let mut blocks = self.thinking.clone();
+        if !self.text.is_empty() {
+            blocks.push(InputContentBlock::text(self.text.clone()));
+        }
+        blocks.extend(self.tool_calls.iter().map(ToolCall::to_block));
+        MessageContent::Blocks(blocks)
+    }
+
+    /// The text of each thinking block; redacted ones have none to show
+    pub fn thoughts(&self) -> Vec<&str> {
+        self.thinking
+            .iter()
+            .filter_map(|block| match block {
+                InputContentBlock::Thinking { thinking, .. } => Some(thinking.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Lets a program follow what a [`Client`] does on the way to a reply, e.g. to tell the user
+/// why it is waiting. Every method does nothing by default.
+pub trait Hooks: Send + Sync {
+    /// Before each request goes out; an error stops it from being sent
+    fn before_send(&self) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    /// With each response, retried or not, before its body is read
+    fn on_response(&self, _response: &reqwest::Response) {}
+
+    /// Before waiting `delay` to send again after `status`; `attempt` counts from 1
+    fn on_retry(&self, _status: StatusCode, _delay: Duration, _attempt: u32) {}
+
+    /// Before asking again for a response that was neither a reply nor an API error
+    fn on_malformed_retry(&self, _error: &AppError, _attempt: u32, _retries: u32) {}
+}
+
+struct NoHooks;
+
+impl Hooks for NoHooks {}
+
+/// Sends conversations to the Messages API with one API key and one set of [`Settings`].
+/// Rate limits, overloads and server errors are retried, honoring Retry-After, and so is a
+/// response cut short on the way.
+///
+/// ```no_run
+/// use anthropic_in_a_shell::{ClaudeMessage, Client};
+///
+/// # async fn run() -> Result<(), anthropic_in_a_shell::AppError> {
+/// let client = Client::new("sk-ant-...");
+/// let reply = client.send(&[ClaudeMessage::user("Hello, Claude")]).await?;
+/// println!("{}", reply.text);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    api_key: String,
+    base_url: String,
+    api_version: String,
+    // Comma-separated beta feature names for the anthropic-beta header
+    beta: Option<String>,
+    timeout: Option<Duration>,
+    malformed_retries: u32,
+    // Shared by the clones of a client, so all of them keep to its pace
+    throttle: Option<Arc<Throttle>>,
+    hooks: Arc<dyn Hooks>,
+    pub settings: Settings,
+}
+
+impl Client {
+    /// A client for the public API with the default settings
+    pub fn new(api_key: impl Into<String>) -> Self {
+        Client {
+            http: reqwest::Client::new(),
+            api_key: api_key.into(),
+            base_url: DEFAULT_BASE_URL.to_string(),
+            api_version: DEFAULT_API_VERSION.to_string(),
+            beta: None,
+            timeout: None,
+            malformed_retries: DEFAULT_MALFORMED_RETRIES,
+            throttle: None,
+            hooks: Arc::new(NoHooks),
+            settings: Settings::default(),
+        }
+    }
+
+    /// Send requests through this HTTP client instead, e.g. one with a proxy
+    pub fn with_http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Talk to a gateway or Anthropic-compatible proxy instead of the public API
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// The anthropic-version header to send
+    pub fn with_api_version(mut self, api_version: impl Into<String>) -> Self {
+        self.api_version = api_version.into();
+        self
+    }
+
+    /// Comma-separated beta features to opt into with the anthropic-beta header
+    pub fn with_beta(mut self, beta: impl Into<String>) -> Self {
+        self.beta = Some(beta.into());
+        self
+    }
+
+    /// How long to wait for a reply; when streaming, for each piece of it
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// How many times to ask again for a response that is neither a reply nor an API error
+    pub fn with_malformed_retries(mut self, retries: u32) -> Self {
+        self.malformed_retries = retries;
+        self
+    }
+
+    /// Space requests out, this client's and its clones' alike
+    pub fn with_throttle(mut self, throttle: Throttle) -> Self {
+        self.throttle = Some(Arc::new(throttle));
+        self
+    }
+
+    pub fn with_hooks(mut self, hooks: impl Hooks + 'static) -> Self {
+        self.hooks = Arc::new(hooks);
+        self
+    }
+
+    pub fn with_settings(mut self, settings: Settings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// The URL of an API endpoint such as `messages`. The base URL may have a trailing
+    /// slash, and `/v1` or not.
+    pub fn endpoint(&self, path: &str) -> String {
+        let base = self.base_url.trim_end_matches('/');
+        if base.ends_with("/v1") {
+            format!("{}/{}", base, path)
+        } else {
+            format!("{}/v1/{}", base, path)
+        }
+    }
+
+    pub fn messages_url(&self) -> String {
+        self.endpoint("messages")
+    }
+
+    /// The request body that `send` (or with `stream`, `send_streaming`) would post
+    pub fn request(&self, messages: &[ClaudeMessage], stream: bool) -> ClaudeApiRequest {
+        let settings = &self.settings;
+        let mut messages: Vec<ClaudeMessage> = messages
+            .iter()
+            .map(|message| ClaudeMessage {
+                tags: Vec::new(),
+                ..message.clone()
+            })
+            .collect();
+        if settings.cache_first_turn {
+            if let Some(first) = messages.first_mut() {
+                first.content = first.content.cached();
+            }
+        }
+        let system = settings.system.clone().map(MessageContent::from);
+        ClaudeApiRequest {
+            model: settings.model.clone(),
+            // Thinking counts against max_tokens, so the answer keeps all of its own
+            max_tokens: settings.max_tokens + settings.thinking.unwrap_or(0),
+            messages,
+            system: match system {
+                Some(system) if settings.cache_system => Some(system.cached()),
+                system => system,
+            },
+            temperature: settings.temperature,
+            top_p: settings.top_p,
+            // Left out entirely rather than sent as an empty list
+            stop_sequences: (!settings.stop_sequences.is_empty())
+                .then(|| settings.stop_sequences.clone()),
+            tools: (!settings.tools.is_empty()).then(|| settings.tools.clone()),
+            metadata: settings.user_id.clone().map(|user_id| Metadata { user_id }),
+            thinking: settings.thinking.map(Thinking::enabled),
+            stream,
+        }
+    }
+
+    /// The request as it goes out, for logs and dry runs; the key is never shown
+    pub fn describe(&self, request: &ClaudeApiRequest) -> String {
+        format!(
+            "POST {}\n  x-api-key: [REDACTED]\n  anthropic-version: {}{}\n  content-type: application/json\n{}",
+            self.messages_url(),
+            self.api_version,
+            self.beta
+                .as_ref()
+                .map(|beta| format!("\n  anthropic-beta: {}", beta))
+                .unwrap_or_default(),
+            serde_json::to_string_pretty(request).unwrap_or_default()
+        )
+    }
+
+    // Authentication and versioning headers shared by every API call
+    fn headers(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        let request = request
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", &self.api_version);
+        match &self.beta {
+            Some(beta) => request.header("anthropic-beta", beta),
+            None => request,
+        }
+    }
+
+    async fn post(&self, request: &ClaudeApiRequest) -> Result<reqwest::Response, AppError> {
+        let mut attempt = 0;
+        loop {
+            self.hooks.before_send()?;
+            if let Some(throttle) = &self.throttle {
+                throttle.wait().await;
+            }
+            log::debug!("{}", self.describe(request));
+            let started = Instant::now();
+            let mut builder = self
+                .headers(self.http.post(self.messages_url()))
+                .json(request);
+            // A stream is instead given the timeout for each of its chunks
+            if let Some(timeout) = self.timeout.filter(|_| !request.stream) {
+                builder = builder.timeout(timeout);
+            }
+            let response = within(self.timeout, builder.send()).await?;
+            log::debug!(
+                "{} after {:.2?}\n{}",
+                response.status(),
+                started.elapsed(),
+                response
+                    .headers()
+                    .iter()
+                    .map(|(name, value)| format!(
+                        "  {}: {}",
+                        name,
+                        value.to_str().unwrap_or("<binary>")
+                    ))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
+            self.hooks.on_response(&response);
+            if !is_retryable(response.status()) || attempt >= MAX_RETRIES {
+                return Ok(response);
+            }
+            let delay = retry_delay(response.headers(), attempt);
+            attempt += 1;
+            log::debug!(
+                "{}, retrying in {}s ({}/{})",
+                response.status(),
+                delay.as_secs(),
+                attempt,
+                MAX_RETRIES
+            );
+            self.hooks.on_retry(response.status(), delay, attempt);
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    // Post the request until it gets a parsed reply, an error, or (when streaming) an event
+    // stream to read it from
+    async fn exchange(
+        &self,
+        request: &ClaudeApiRequest,
+    ) -> Result<Result<Reply, reqwest::Response>, AppError> {
+        let mut malformed = 0;
+        loop {
+            let response = self.post(request).await?;
+            // Errors, and some proxies, answer a streamed request with a plain JSON body
+            if request.stream && is_event_stream(response.headers()) {
+                return Ok(Err(response));
+            }
+            let status = response.status();
+            let id = request_id(response.headers());
+            let body = within(self.timeout, response.text()).await?;
+            match parse_reply(status, &body) {
+                Ok(reply) => {
+                    return Ok(Ok(Reply {
+                        request_id: id,
+                        ..reply
+                    }))
+                }
+                Err(e) if is_malformed(&e) && malformed < self.malformed_retries => {
+                    malformed += 1;
+                    log::debug!("{}, retrying ({}/{})", e, malformed, self.malformed_retries);
+                    self.hooks
+                        .on_malformed_retry(&e, malformed, self.malformed_retries);
+                }
+                Err(e) => return Err(e.with_request_id(id.as_deref())),
+            }
+        }
+    }
+
+    /// Send the conversation and wait for the whole reply
+    pub async fn send(&self, messages: &[ClaudeMessage]) -> Result<Reply, AppError> {
+        let started = Instant::now();
+        match self.exchange(&self.request(messages, false)).await? {
+            Ok(reply) => Ok(Reply {
+                elapsed: started.elapsed(),
+                ..reply
+            }),
+            Err(_) => unreachable!("only streamed requests are read as a stream"),
+        }
+    }
+
+    /// Send the conversation and hand each piece of the reply to `on_text` as it arrives.
+    /// The reply returned at the end has all of it.
+    pub async fn send_streaming(
+        &self,
+        messages: &[ClaudeMessage],
+        mut on_text: impl FnMut(&str),
+    ) -> Result<Reply, AppError> {
+        let mut stream = match self.stream(messages).await? {
+            Streaming::Complete(reply) => {
+                on_text(&reply.text);
+                return Ok(reply);
+            }
+            Streaming::Events(stream) => stream,
+        };
+        while let Some(event) = stream.next().await? {
+            if let Some(text) = text_delta(&event) {
+                on_text(text);
+            }
+        }
+        if !stream.is_complete() {
+            return Err(AppError::Network(
+                "The stream ended before the reply was complete".to_string(),
+            ));
+        }
+        Ok(stream.finish())
+    }
+
+    /// Send the conversation for a streamed reply, and read it an event at a time; for
+    /// programs that show more of it than the text, such as thinking
+    pub async fn stream(&self, messages: &[ClaudeMessage]) -> Result<Streaming, AppError> {
+        let started = Instant::now();
+        let request = self.request(messages, true);
+        Ok(match self.exchange(&request).await? {
+            Ok(reply) => Streaming::Complete(Reply {
+                elapsed: started.elapsed(),
+                ..reply
+            }),
+            Err(response) => Streaming::Events(ReplyStream {
+                streamed: StreamedReply::new(&request.model, request_id(response.headers())),
+                response,
+                events: EventStream::default(),
+                queued: VecDeque::new(),
+                timeout: self.timeout,
+                started,
+                first_token: None,
+            }),
+        })
+    }
+
+    /// The models this key can use
+    pub async fn models(&self) -> Result<Vec<ModelEntry>, AppError> {
+        self.hooks.before_send()?;
+        let mut request = self.headers(self.http.get(self.endpoint("models?limit=1000")));
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
+        }
+        let response = within(self.timeout, request.send()).await?;
+        let status = response.status();
+        let id = request_id(response.headers());
+        let body = within(self.timeout, response.text()).await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body, None).with_request_id(id.as_deref()));
+        }
+        serde_json::from_str::<ModelList>(&body)
+            .map(|list| list.data)
+            .map_err(|e| AppError::Deserialize {
+                status: status.as_u16(),
+                message: format!("could not read the model list: {}", e),
+                request_id: id,
+            })
+    }
+}
+
+/// What [`Client::stream`] gets back: a whole reply, which is how errors and some proxies
+/// answer, or the events to build one from
+// There is one of these a request, so the size of its variants doesn't matter
+#[allow(clippy::large_enum_variant)]
+pub enum Streaming {
+    Complete(Reply),
+    Events(ReplyStream),
+}
+
+/// The events of a streamed reply, each added to the reply before it is handed out
+pub struct ReplyStream {
+    response: reqwest::Response,
+    events: EventStream,
+    // Events from the last chunk that haven't been handed out yet
+    queued: VecDeque<StreamEvent>,
+    streamed: StreamedReply,
+    timeout: Option<Duration>,
+    started: Instant,
+    first_token: Option<Duration>,
+}
+
+impl ReplyStream {
+    /// The next event, or None once the reply is complete or the stream ends without it.
+    /// An error event is returned as the error. Dropping the future before it is ready
+    /// loses nothing, so it can race a timer in `select!`.
+    pub async fn next(&mut self) -> Result<Option<StreamEvent>, AppError> {
+        loop {
+            if self.streamed.is_complete() {
+                return Ok(None);
+            }
+            if let Some(event) = self.queued.pop_front() {
+                if text_delta(&event).is_some() && self.first_token.is_none() {
+                    self.first_token = Some(self.started.elapsed());
+                }
+                self.streamed.push(&event)?;
+                return Ok(Some(event));
+            }
+            match within(self.timeout, self.response.chunk()).await? {
+                Some(chunk) => self.queued.extend(self.events.push(&chunk)),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// The reply so far
+    pub fn reply(&self) -> &Reply {
+        self.streamed.reply()
+    }
+
+    /// Whether the message_stop event has arrived
+    pub fn is_complete(&self) -> bool {
+        self.streamed.is_complete()
+    }
+
+    /// The reply, with what arrived of it if the stream ended early
+    pub fn finish(self) -> Reply {
+        Reply {
+            elapsed: self.started.elapsed(),
+            first_token: self.first_token,
+            ..self.streamed.finish()
+        }
+    }
+}
+
+// The text an event adds to the reply, if it is a text delta
+fn text_delta(event: &StreamEvent) -> Option<&str> {
+    match event {
+        StreamEvent::ContentBlockDelta { delta, .. } if delta.delta_type == "text_delta" => {
+            Some(&delta.text)
+        }
+        _ => None,
+    }
+}
+
+// Wait for a request, or a piece of its response, for at most `timeout`
+async fn within<T>(
+    timeout: Option<Duration>,
+    future: impl Future<Output = reqwest::Result<T>>,
+) -> Result<T, AppError> {
+    let result = match timeout {
+        Some(limit) => tokio::time::timeout(limit, future)
+            .await
+            .map_err(|_| timed_out(timeout))?,
+        None => future.await,
+    };
+    result.map_err(|e| {
+        if e.is_timeout() {
+            timed_out(timeout)
+        } else if e.is_connect() {
+            // DNS failures and refused connections look alike to the user; the detail is
+            // in the log
+            log::debug!("Connection failed: {:?}", e);
+            AppError::Network(
+                "Could not reach the Anthropic API — check your connection, base URL or proxy."
+                    .to_string(),
+            )
+        } else {
+            AppError::Network(e.to_string())
+        }
+    })
+}
+
+fn timed_out(timeout: Option<Duration>) -> AppError {
+    AppError::Network(match timeout {
+        Some(timeout) => format!("Request timed out after {}s", timeout.as_secs()),
+        None => "Request timed out".to_string(),
+    })
+}
+
+/// Rate limits, overloads and transient server errors are worth retrying
+pub fn is_retryable(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 429 | 500 | 502 | 503 | 529)
+}
+
+/// How long to wait before retry number `attempt + 1`: what Retry-After asks for, up to
+/// `MAX_RETRY_DELAY`, or else 1s, 2s, 4s, ...
+pub fn retry_delay(headers: &HeaderMap, attempt: u32) -> Duration {
+    let retry_after = headers
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+    match retry_after {
+        Some(seconds) => Duration::from_secs(seconds).min(MAX_RETRY_DELAY),
+        None => Duration::from_secs(1 << attempt),
+    }
+}
+
+/// The request-id header, which Anthropic support asks for to look into a request
+pub fn request_id(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get("request-id")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Whether a response is a stream of server-sent events rather than a JSON body
+pub fn is_event_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"))
+}
+
+/// Whether an error is a successful response whose body wasn't a reply. That is usually one
+/// a gateway cut short, so it is worth asking again. Error statuses aren't: asking again
+/// won't fix a bad key or request just because a proxy sent back its own page for it.
+pub fn is_malformed(error: &AppError) -> bool {
+    matches!(error, AppError::Deserialize { status, .. } if (200..300).contains(status))
+}
+
+/// A complete response body: the reply, or the error the API sent instead. The request-id,
+/// which is in the headers, is left for the caller to add.
+pub fn parse_reply(status: StatusCode, body: &str) -> Result<Reply, AppError> {
+    let mut parse_error = None;
+    if status.is_success() {
+        match serde_json::from_str::<ClaudeApiResponse>(body) {
+            Ok(response) => {
+                return Ok(Reply {
+                    // Every text block in order, so an answer split around a tool call
+                    // or across blocks arrives whole
+                    text: response
+                        .content
+                        .iter()
+                        .filter(|item| item.content_type == "text")
+                        .map(|item| item.text.as_str())
+                        .collect(),
+                    tool_calls: response
+                        .content
+                        .iter()
+                        .filter(|item| item.content_type == "tool_use")
+                        .map(|item| ToolCall {
+                            id: item.id.clone().unwrap_or_default(),
+                            name: item.name.clone().unwrap_or_default(),
+                            input: item.input.clone().unwrap_or_default(),
+                        })
+                        .collect(),
+                    thinking: response
+                        .content
+                        .iter()
+                        .filter_map(|item| match item.content_type.as_str() {
+                            "thinking" => Some(InputContentBlock::Thinking {
+                                thinking: item.thinking.clone(),
+                                signature: item.signature.clone().unwrap_or_default(),
+                            }),
+                            "redacted_thinking" => Some(InputContentBlock::RedactedThinking {
+                                data: item.data.clone().unwrap_or_default(),
+                            }),
+                            _ => None,
+                        })
+                        .collect(),
+                    model: response.model,
+                    stop_reason: response.stop_reason,
+                    stop_sequence: response.stop_sequence,
+                    input_tokens: response.usage.input_tokens.unwrap_or(0),
+                    output_tokens: response.usage.output_tokens.unwrap_or(0),
+                    cache_write_tokens: response.usage.cache_creation_input_tokens.unwrap_or(0),
+                    cache_read_tokens: response.usage.cache_read_input_tokens.unwrap_or(0),
+                    ..Reply::default()
+                });
+            }
+            Err(e) => parse_error = Some(e),
+        }
+    }
+    Err(api_error(status, body, parse_error))
+}
+
+// What an unsuccessful (or unreadable) response body says went wrong
+fn api_error(status: StatusCode, body: &str, parse_error: Option<serde_json::Error>) -> AppError {
+    // A refused key gets its own error, apart from rate limits and server errors, since
+    // the fix is on the user's side; the API's wording only goes to the log
+    let unauthorized = status == StatusCode::UNAUTHORIZED;
+    if let Ok(api_error) = serde_json::from_str::<ClaudeApiError>(body) {
+        if unauthorized || api_error.error.error_type == "authentication_error" {
+            log::debug!("Authentication failed: {}", api_error.error.message);
+            return AppError::Auth;
+        }
+        return api_error.error.into();
+    }
+    if unauthorized {
+        return AppError::Auth;
+    }
+    // The whole body only goes to the log; the error carries enough to recognise it
+    log::debug!("Unparseable response body:\n{}", body);
+    const EXCERPT: usize = 200;
+    let message = match parse_error {
+        Some(e) => format!("could not read the reply: {}", e),
+        None if body.chars().count() > EXCERPT => {
+            format!("{}...", body.chars().take(EXCERPT).collect::<String>())
+        }
+        None => body.to_string(),
+    };
+    AppError::Deserialize {
+        status: status.as_u16(),
+        message,
+        request_id: None,
+    }
+}
+
+/// Splits a streamed response into its events. Chunks don't line up with SSE lines (or
+/// UTF-8 characters), so a partial line is kept until the rest of it arrives.
+#[derive(Default)]
+pub struct EventStream {
+    pending: Vec<u8>,
+}
+
+impl EventStream {
+    /// The events completed by this chunk
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<StreamEvent> {
+        self.pending.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(newline) = self.pending.iter().position(|&byte| byte == b'\n') {
+            let line_bytes: Vec<u8> = self.pending.drain(..=newline).collect();
+            let line = String::from_utf8_lossy(&line_bytes);
+            events.extend(parse_sse_line(line.trim_end()));
+        }
+        events
+    }
+}
+
+/// Builds a [`Reply`] from the events of a streamed one
+pub struct StreamedReply {
+    reply: Reply,
+    // The tool_use block being streamed, by index, and its input JSON so far
+    tool_input: Option<(usize, String)>,
+    complete: bool,
+}
+
+impl StreamedReply {
+    /// `model` stands in until the stream says which one answered
+    pub fn new(model: &str, request_id: Option<String>) -> Self {
+        StreamedReply {
+            reply: Reply {
+                model: model.to_string(),
+                request_id,
+                ..Reply::default()
+            },
+            tool_input: None,
+            complete: false,
+        }
+    }
+
+    /// Add what an event says to the reply. An error event is returned as the error, with
+    /// the request-id.
+    pub fn push(&mut self, event: &StreamEvent) -> Result<(), AppError> {
+        let reply = &mut self.reply;
+        match event {
+            StreamEvent::MessageStart { message } => {
+                reply.input_tokens = message.usage.input_tokens.unwrap_or(0);
+                reply.cache_write_tokens = message.usage.cache_creation_input_tokens.unwrap_or(0);
+                reply.cache_read_tokens = message.usage.cache_read_input_tokens.unwrap_or(0);
+                reply.model = message.model.clone();
+            }
+            StreamEvent::ContentBlockStart {
+                index,
+                content_block,
+            } => match content_block.block_type.as_str() {
+                "tool_use" => {
+                    reply.tool_calls.push(ToolCall {
+                        id: content_block.id.clone().unwrap_or_default(),
+                        name: content_block.name.clone().unwrap_or_default(),
+                        input: serde_json::Value::Null,
+                    });
+                    self.tool_input = Some((*index, String::new()));
+                }
+                "thinking" => reply.thinking.push(InputContentBlock::Thinking {
+                    thinking: String::new(),
+                    signature: String::new(),
+                }),
+                "redacted_thinking" => reply.thinking.push(InputContentBlock::RedactedThinking {
+                    data: content_block.data.clone().unwrap_or_default(),
+                }),
+                _ => {}
+            },
+            StreamEvent::ContentBlockDelta { delta, .. } => match delta.delta_type.as_str() {
+                "text_delta" => reply.text.push_str(&delta.text),
+                "input_json_delta" => {
+                    if let Some((_, json)) = self.tool_input.as_mut() {
+                        json.push_str(&delta.partial_json);
+                    }
+                }
+                "thinking_delta" | "signature_delta" => {
+                    if let Some(InputContentBlock::Thinking {
+                        thinking,
+                        signature,
+                    }) = reply.thinking.last_mut()
+                    {
+                        thinking.push_str(&delta.thinking);
+                        signature.push_str(&delta.signature);
+                    }
+                }
+                _ => {}
+            },
+            StreamEvent::ContentBlockStop { index }
+                if self
+                    .tool_input
+                    .as_ref()
+                    .is_some_and(|(tool, _)| tool == index) =>
+            {
+                let (_, json) = self.tool_input.take().unwrap_or_default();
+                if let Some(call) = reply.tool_calls.last_mut() {
+                    // A tool without parameters may send no input at all
+                    call.input = if json.trim().is_empty() {
+                        serde_json::json!({})
+                    } else {
+                        serde_json::from_str(&json).map_err(|e| AppError::Deserialize {
+                            status: 200,
+                            message: format!("could not read the input for {}: {}", call.name, e),
+                            request_id: reply.request_id.clone(),
+                        })?
+                    };
+                }
+            }
+            StreamEvent::MessageDelta { delta, usage } => {
+                reply.output_tokens = usage.output_tokens.unwrap_or(0);
+                reply.stop_reason = delta.stop_reason.clone();
+                reply.stop_sequence = delta.stop_sequence.clone();
+            }
+            StreamEvent::MessageStop => self.complete = true,
+            StreamEvent::Error { error } => {
+                return Err(
+                    AppError::from((*error).clone()).with_request_id(reply.request_id.as_deref())
+                )
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// The reply so far
+    pub fn reply(&self) -> &Reply {
+        &self.reply
+    }
+
+    /// Whether the message_stop event has arrived
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    pub fn finish(self) -> Reply {
+        self.reply
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streamed_events_make_a_reply() {
+        let lines = [
+            r#"{"type":"message_start","message":{"id":"m","type":"message","role":"assistant","content":[],"model":"claude-x","stop_reason":null,"stop_sequence":null,"usage":{"input_tokens":9,"output_tokens":1}}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Let me "}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"check."}}"#,
+            r#"{"type":"content_block_start","index":1,"content_block":{"type":"tool_use","id":"t1","name":"calculator"}}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"{\"expression\": "}}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"\"1 + 1\"}"}}"#,
+            r#"{"type":"content_block_stop","index":1}"#,
+            r#"{"type":"message_delta","delta":{"stop_reason":"tool_use","stop_sequence":null},"usage":{"output_tokens":20}}"#,
+            r#"{"type":"message_stop"}"#,
+        ];
+        let body: String = lines
+            .iter()
+            .map(|line| format!("event: x\ndata: {}\n\n", line))
+            .collect();
+        // Chunks split lines, and characters, anywhere
+        let mut events = EventStream::default();
+        let mut streamed = StreamedReply::new("claude-default", Some("req_1".to_string()));
+        for chunk in body.as_bytes().chunks(7) {
+            for event in events.push(chunk) {
+                streamed.push(&event).unwrap();
+            }
+        }
+        assert!(streamed.is_complete());
+        let reply = streamed.finish();
+        assert_eq!(reply.text, "Let me check.");
+        assert_eq!(reply.model, "claude-x");
+        assert_eq!(reply.stop_reason.as_deref(), Some("tool_use"));
+        assert_eq!((reply.input_tokens, reply.output_tokens), (9, 20));
+        assert_eq!(reply.tool_calls.len(), 1);
+        assert_eq!(reply.tool_calls[0].input["expression"], "1 + 1");
+        assert_eq!(reply.request_id.as_deref(), Some("req_1"));
+    }
+
+    #[test]
+    fn requests_carry_the_settings() {
+        let client = Client::new("key").with_settings(Settings {
+            system: Some("Be brief.".to_string()),
+            thinking: Some(2000),
+            user_id: Some("user-7".to_string()),
+            cache_system: true,
+            cache_first_turn: true,
+            ..Settings::default()
+        });
+        let request = serde_json::to_value(client.request(
+            &[
+                ClaudeMessage::user("Hi".to_string()),
+                ClaudeMessage::assistant("Hello".to_string()),
+            ],
+            false,
+        ))
+        .unwrap();
+        assert_eq!(request["metadata"]["user_id"], "user-7");
+        assert_eq!(request["max_tokens"], DEFAULT_MAX_TOKENS + 2000);
+        assert_eq!(request["system"][0]["cache_control"]["type"], "ephemeral");
+        let turns = request["messages"].as_array().unwrap();
+        assert_eq!(turns[0]["content"][0]["cache_control"]["type"], "ephemeral");
+        assert_eq!(turns[1]["content"], "Hello");
+        assert!(turns.iter().all(|turn| turn.get("tags").is_none()));
+        assert!(request.get("stop_sequences").is_none());
+    }
+
+    #[test]
+    fn retry_after_is_honored_up_to_a_minute() {
+        let cases = [
+            (None, 0, 1),
+            (None, 2, 4),
+            (Some("5"), 0, 5),
+            (Some(" 30 "), 2, 30),
+            (Some("86400"), 0, 60),
+            (Some("Wed, 21 Oct 2015 07:28:00 GMT"), 1, 2),
+        ];
+        for (retry_after, attempt, seconds) in cases {
+            let mut headers = HeaderMap::new();
+            if let Some(value) = retry_after {
+                headers.insert(reqwest::header::RETRY_AFTER, value.parse().unwrap());
+            }
+            assert_eq!(
+                retry_delay(&headers, attempt),
+                Duration::from_secs(seconds),
+                "{:?}",
+                retry_after
+            );
+        }
+    }
+
+    #[test]
+    fn only_successful_bodies_that_dont_parse_are_malformed() {
+        let cases = [
+            (200, "{\"content\": [", true),
+            (200, "<html>", true),
+            (400, "<html>Bad Request</html>", false),
+            (401, "", false),
+            (502, "<html>Bad Gateway</html>", false),
+        ];
+        for (status, body, malformed) in cases {
+            let status = reqwest::StatusCode::from_u16(status).unwrap();
+            let error = parse_reply(status, body).unwrap_err();
+            assert_eq!(is_malformed(&error), malformed, "{} {}", status, body);
+        }
+        let api_error =
+            r#"{"type":"error","error":{"type":"invalid_request_error","message":"no"}}"#;
+        let error = parse_reply(reqwest::StatusCode::BAD_REQUEST, api_error).unwrap_err();
+        assert!(!is_malformed(&error));
+    }
+}
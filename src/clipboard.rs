@@ -0,0 +1,70 @@
+// Copying text to the system clipboard through the platform's own command line tools,
+// which also covers Wayland and X11 without linking against either
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+// Tried in order; the first one that runs wins
+fn candidates() -> Vec<(&'static str, &'static [&'static str])> {
+    if cfg!(target_os = "macos") {
+        vec![("pbcopy", &[])]
+    } else if cfg!(windows) {
+        vec![("clip", &[])]
+    } else {
+        let mut tools: Vec<(&'static str, &'static [&'static str])> = Vec::new();
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            tools.push(("wl-copy", &[]));
+        }
+        if std::env::var_os("DISPLAY").is_some() {
+            tools.push(("xclip", &["-selection", "clipboard"]));
+            tools.push(("xsel", &["--clipboard", "--input"]));
+        }
+        tools
+    }
+}
+
+fn run(program: &str, args: &[&str], text: &str) -> io::Result<()> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes())?;
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "{} exited with {}",
+            program, status
+        )));
+    }
+    Ok(())
+}
+
+/// Put `text` on the clipboard
+pub fn copy(text: &str) -> io::Result<()> {
+    let tools = candidates();
+    if tools.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no clipboard available (no display found; is this a headless session?)",
+        ));
+    }
+    let mut last_error = None;
+    for (program, args) in &tools {
+        match run(program, args, text) {
+            Ok(()) => return Ok(()),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    let names: Vec<&str> = tools.iter().map(|(program, _)| *program).collect();
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!(
+            "could not run {} ({})",
+            names.join(" or "),
+            last_error.map_or_else(String::new, |e| e.to_string())
+        ),
+    ))
+}
@@ -0,0 +1,354 @@
+// Tab-completion scripts for `claude-cli completions <shell>`, generated from the clap
+// definitions so new subcommands and flags are picked up without touching this file. Each
+// script works out which (sub)command is being typed from the words so far, a "scope" such
+// as `/` or `/profile/add`, and offers that scope's subcommands and flags.
+use clap::Command;
+use std::fmt::Write;
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+struct Flag {
+    long: Option<String>,
+    short: Option<char>,
+    help: String,
+    takes_value: bool,
+    // The choices of an enum-valued flag such as --color
+    values: Vec<String>,
+}
+
+impl Flag {
+    fn words(&self) -> Vec<String> {
+        let long = self.long.iter().map(|long| format!("--{}", long));
+        let short = self.short.iter().map(|short| format!("-{}", short));
+        long.chain(short).collect()
+    }
+}
+
+struct Subcommand {
+    // The name first, then any aliases
+    names: Vec<String>,
+    about: String,
+}
+
+struct Scope {
+    key: String,
+    flags: Vec<Flag>,
+    subcommands: Vec<Subcommand>,
+}
+
+// First line only; the rest of a long help doesn't fit a completion menu
+fn first_line(text: Option<&clap::builder::StyledStr>) -> String {
+    text.map(|text| text.to_string())
+        .unwrap_or_default()
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_string()
+}
+
+fn collect(command: &Command, key: String, scopes: &mut Vec<Scope>) {
+    let flags = command
+        .get_arguments()
+        .filter(|arg| !arg.is_positional() && !arg.is_hide_set())
+        .map(|arg| Flag {
+            long: arg.get_long().map(str::to_string),
+            short: arg.get_short(),
+            help: first_line(arg.get_help()),
+            takes_value: arg.get_action().takes_values(),
+            values: arg
+                .get_possible_values()
+                .iter()
+                .filter(|value| !value.is_hide_set())
+                .map(|value| value.get_name().to_string())
+                .collect(),
+        })
+        .collect();
+    let subcommands = command
+        .get_subcommands()
+        .filter(|subcommand| !subcommand.is_hide_set())
+        .map(|subcommand| Subcommand {
+            names: std::iter::once(subcommand.get_name())
+                .chain(subcommand.get_all_aliases())
+                .map(str::to_string)
+                .collect(),
+            about: first_line(subcommand.get_about()),
+        })
+        .collect();
+    scopes.push(Scope {
+        key: key.clone(),
+        flags,
+        subcommands,
+    });
+    // `help` repeats the whole tree below it; its own name is enough
+    for subcommand in command.get_subcommands() {
+        if !subcommand.is_hide_set() && subcommand.get_name() != "help" {
+            let child = format!("{}/{}", key.trim_end_matches('/'), subcommand.get_name());
+            collect(subcommand, child, scopes);
+        }
+    }
+}
+
+/// The completion script for `bin`, the name the executable is run as
+pub fn generate(shell: Shell, command: &mut Command, bin: &str) -> String {
+    // Builds in the global flags of each subcommand, and the help ones
+    command.build();
+    let mut scopes = Vec::new();
+    collect(command, "/".to_string(), &mut scopes);
+    let function: String = bin
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    match shell {
+        Shell::Bash => bash(&scopes, bin, &function),
+        Shell::Zsh => zsh(&scopes, bin, &function),
+        Shell::Fish => fish(&scopes, bin, &function),
+    }
+}
+
+// The `case` branches for the word after a flag that takes a value, matched on
+// "scope,flag". A flag means the same in every scope it appears in, so one branch each.
+fn value_cases(scopes: &[Scope], indent: &str, action: impl Fn(&Flag) -> String) -> String {
+    let mut out = String::new();
+    let mut seen = std::collections::HashSet::new();
+    for flag in scopes.iter().flat_map(|scope| &scope.flags) {
+        let words = flag.words();
+        if !flag.takes_value || !seen.insert(words.clone()) {
+            continue;
+        }
+        let patterns: Vec<String> = words.iter().map(|word| format!("*,{}", word)).collect();
+        let _ = writeln!(
+            out,
+            "{0}{1})\n{0}    {2}\n{0}    return ;;",
+            indent,
+            patterns.join("|"),
+            action(flag)
+        );
+    }
+    out
+}
+
+// The `case` branches that step from one scope into a subcommand's, matched on "scope,word"
+fn scope_transitions(scopes: &[Scope], indent: &str) -> String {
+    let mut out = String::new();
+    for scope in scopes {
+        for subcommand in &scope.subcommands {
+            let child = format!(
+                "{}/{}",
+                scope.key.trim_end_matches('/'),
+                subcommand.names[0]
+            );
+            let patterns: Vec<String> = subcommand
+                .names
+                .iter()
+                .map(|name| format!("\"{},{}\"", scope.key, name))
+                .collect();
+            let _ = writeln!(
+                out,
+                "{}{}) scope=\"{}\" ;;",
+                indent,
+                patterns.join("|"),
+                child
+            );
+        }
+    }
+    out
+}
+
+fn bash(scopes: &[Scope], bin: &str, function: &str) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "_{}() {{", function);
+    out.push_str(
+        "    local cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n",
+    );
+    out.push_str("    local scope=\"/\" word opts\n");
+    out.push_str("    for word in \"${COMP_WORDS[@]:1:COMP_CWORD-1}\"; do\n");
+    out.push_str("        case \"$scope,$word\" in\n");
+    out.push_str(&scope_transitions(scopes, "            "));
+    out.push_str("        esac\n    done\n");
+
+    // A flag's value: its choices, or the shell's own file completion
+    out.push_str("    case \"$scope,$prev\" in\n");
+    out.push_str(&value_cases(scopes, "        ", |flag| {
+        if flag.values.is_empty() {
+            "COMPREPLY=()".to_string()
+        } else {
+            format!(
+                "COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+                flag.values.join(" ")
+            )
+        }
+    }));
+    out.push_str("    esac\n");
+
+    out.push_str("    case \"$scope\" in\n");
+    for scope in scopes {
+        let words: Vec<String> = scope
+            .subcommands
+            .iter()
+            .flat_map(|subcommand| subcommand.names.clone())
+            .chain(scope.flags.iter().flat_map(Flag::words))
+            .collect();
+        let _ = writeln!(
+            out,
+            "        \"{}\") opts=\"{}\" ;;",
+            scope.key,
+            words.join(" ")
+        );
+    }
+    out.push_str("    esac\n");
+    out.push_str("    COMPREPLY=($(compgen -W \"$opts\" -- \"$cur\"))\n}\n");
+    let _ = writeln!(out, "complete -o default -F _{} {}", function, bin);
+    out
+}
+
+// Inside single quotes, for zsh and bash alike
+fn single_quoted(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+fn zsh(scopes: &[Scope], bin: &str, function: &str) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "#compdef {}\n", bin);
+    let _ = writeln!(out, "_{}() {{", function);
+    out.push_str("    local scope=\"/\" word i\n");
+    out.push_str("    local -a items\n");
+    out.push_str("    for ((i = 2; i < CURRENT; i++)); do\n");
+    out.push_str("        word=${words[i]}\n");
+    out.push_str("        case \"$scope,$word\" in\n");
+    out.push_str(&scope_transitions(scopes, "            "));
+    out.push_str("        esac\n    done\n");
+
+    out.push_str("    case \"$scope,${words[CURRENT-1]}\" in\n");
+    out.push_str(&value_cases(scopes, "        ", |flag| {
+        if flag.values.is_empty() {
+            "_files".to_string()
+        } else {
+            format!("_values 'value' {}", flag.values.join(" "))
+        }
+    }));
+    out.push_str("    esac\n");
+
+    // `name:description` pairs; a colon in the name itself is escaped
+    out.push_str("    case \"$scope\" in\n");
+    for scope in scopes {
+        let mut items = Vec::new();
+        for subcommand in &scope.subcommands {
+            for name in &subcommand.names {
+                items.push(format!("{}:{}", name.replace(':', "\\:"), subcommand.about));
+            }
+        }
+        for flag in &scope.flags {
+            for word in flag.words() {
+                items.push(format!("{}:{}", word, flag.help));
+            }
+        }
+        let items: Vec<String> = items.iter().map(|item| single_quoted(item)).collect();
+        let _ = writeln!(
+            out,
+            "        \"{}\") items=({}) ;;",
+            scope.key,
+            items.join(" ")
+        );
+    }
+    out.push_str("    esac\n");
+    let _ = writeln!(out, "    _describe '{}' items\n}}\n", bin);
+    let _ = writeln!(out, "_{} \"$@\"", function);
+    out
+}
+
+// Fish's single quotes only understand \' and \\
+fn fish_quoted(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn fish(scopes: &[Scope], bin: &str, function: &str) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "function __{}_scope", function);
+    out.push_str("    set -l scope /\n");
+    out.push_str("    for word in (commandline -opc)[2..-1]\n");
+    out.push_str("        switch \"$scope,$word\"\n");
+    for scope in scopes {
+        for subcommand in &scope.subcommands {
+            let child = format!(
+                "{}/{}",
+                scope.key.trim_end_matches('/'),
+                subcommand.names[0]
+            );
+            let patterns: Vec<String> = subcommand
+                .names
+                .iter()
+                .map(|name| format!("\"{},{}\"", scope.key, name))
+                .collect();
+            let _ = writeln!(
+                out,
+                "            case {}\n                set scope {}",
+                patterns.join(" "),
+                child
+            );
+        }
+    }
+    out.push_str("        end\n    end\n    echo $scope\nend\n\n");
+    let _ = writeln!(
+        out,
+        "function __{0}_in\n    test (__{0}_scope) = $argv[1]\nend\n",
+        function
+    );
+    let _ = writeln!(out, "complete -c {} -f", bin);
+
+    // Global flags are in every scope, so they go without a condition, once
+    let everywhere = |flag: &Flag| {
+        scopes.iter().all(|scope| {
+            scope
+                .flags
+                .iter()
+                .any(|other| other.words() == flag.words())
+        })
+    };
+    for flag in scopes[0].flags.iter().filter(|flag| everywhere(flag)) {
+        out.push_str(&fish_flag(bin, "", flag));
+    }
+    for scope in scopes {
+        let condition = format!(" -n '__{}_in {}'", function, scope.key);
+        for subcommand in &scope.subcommands {
+            for name in &subcommand.names {
+                let _ = writeln!(
+                    out,
+                    "complete -c {}{} -a {} -d {}",
+                    bin,
+                    condition,
+                    name,
+                    fish_quoted(&subcommand.about)
+                );
+            }
+        }
+        for flag in scope.flags.iter().filter(|flag| !everywhere(flag)) {
+            out.push_str(&fish_flag(bin, &condition, flag));
+        }
+    }
+    out
+}
+
+fn fish_flag(bin: &str, condition: &str, flag: &Flag) -> String {
+    let mut line = format!("complete -c {}{}", bin, condition);
+    if let Some(long) = &flag.long {
+        let _ = write!(line, " -l {}", long);
+    }
+    if let Some(short) = flag.short {
+        let _ = write!(line, " -s {}", short);
+    }
+    if flag.takes_value {
+        if flag.values.is_empty() {
+            line.push_str(" -r -F");
+        } else {
+            let _ = write!(line, " -x -a {}", fish_quoted(&flag.values.join(" ")));
+        }
+    }
+    let _ = writeln!(line, " -d {}", fish_quoted(&flag.help));
+    line
+}
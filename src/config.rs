@@ -0,0 +1,819 @@
+// Settings persisted between runs in config.json, plus the saved conversation history
+use crate::keyring::{self, KeyStore};
+use crate::modules::ClaudeMessage;
+use crate::pricing::Price;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+
+pub const API_KEY_ENV: &str = "ANTHROPIC_API_KEY";
+/// Alternative config file location, below the --config flag in precedence
+pub const CONFIG_ENV: &str = "CLAUDE_CLI_CONFIG";
+
+/// Where an effective setting comes from, for `config show`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Source {
+    Env,
+    Flag,
+    Profile,
+    Keyring,
+    File,
+    Default,
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Source::Env => "env",
+            Source::Flag => "flag",
+            Source::Profile => "profile",
+            Source::Keyring => "keyring",
+            Source::File => "file",
+            Source::Default => "default",
+        })
+    }
+}
+
+/// A named set of settings, e.g. a work key with its own model and system prompt.
+/// Anything left unset falls back to the top-level config. With the keyring as the key
+/// store, the key is kept there rather than in `api_key`.
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct Profile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+}
+
+// Fields without serde(skip) are persisted to config.json
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    api_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub show_usage: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key_store: Option<KeyStore>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_version: Option<String>,
+    // How often to ask again when a reply body can't be parsed at all
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub malformed_retries: Option<u32>,
+    // Sent as the anthropic-beta header, e.g. "prompt-caching-2024-07-31"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anthropic_beta: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    // Estimated history size that triggers the context window warning
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warn_tokens: Option<u32>,
+    // Estimated size of a single message that needs confirming before it is sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confirm_tokens: Option<u32>,
+    // Drop the oldest exchanges before sending once the history passes context_limit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auto_trim: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_limit: Option<u32>,
+    // Bytes of each /file attachment to send before truncating
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_limit: Option<usize>,
+    // Default for --user-id, e.g. set once per person on a shared gateway
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    // Reuse saved replies to identical requests, see cache.rs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache: Option<bool>,
+    // Shown before what the user types and before each reply, in place of the emoji
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_prompt: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assistant_prefix: Option<String>,
+    // Model ids and descriptions for `models` when the API can't be asked
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub models: BTreeMap<String, String>,
+    // Patterns whose matches are replaced before a prompt is sent, see redact.rs
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub redact_patterns: Vec<String>,
+    // Per-model prices that replace or extend the built-in table
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub pricing: BTreeMap<String, Price>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active_profile: Option<String>,
+    // Named system prompts for --persona and /persona
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub personas: BTreeMap<String, String>,
+    // Named prompts that /snippet and :name put in the input line
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub snippets: BTreeMap<String, String>,
+    // Named conversation whose history the chat loop uses; None is the default history
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active_session: Option<String>,
+    // Key from the environment; takes precedence but is never written to disk
+    #[serde(skip)]
+    env_api_key: Option<String>,
+    #[serde(skip)]
+    keyring_api_key: Option<String>,
+    // The active profile's key, when it is kept in the keyring
+    #[serde(skip)]
+    profile_keyring_api_key: Option<String>,
+    #[serde(skip)]
+    config_path: std::path::PathBuf,
+    // Changes stay in memory only
+    #[serde(skip)]
+    read_only: bool,
+}
+
+impl Config {
+    /// Load from `path`, else CLAUDE_CLI_CONFIG, else the platform config directory. History
+    /// and sessions live next to whichever file is used.
+    pub fn new(path: Option<std::path::PathBuf>) -> io::Result<Self> {
+        let from_env = std::env::var_os(CONFIG_ENV)
+            .filter(|value| !value.is_empty())
+            .map(std::path::PathBuf::from);
+        let config_path = match path.or(from_env) {
+            Some(path) => path,
+            None => config_dir()?.join("claude-cli/config.json"),
+        };
+
+        let mut config: Config = match fs::read_to_string(&config_path) {
+            Ok(config_str) if config_str.trim().is_empty() => Config::default(),
+            Ok(config_str) => match serde_json::from_str(&config_str) {
+                Ok(config) => config,
+                Err(e) => set_aside(&config_path, &e),
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Config::default(),
+            // Unreadable isn't the same as missing: saving would replace what's there
+            Err(e) => {
+                eprintln!(
+                    "Warning: could not read {} ({}). Using an empty config and saving no \
+                     changes over it; fix or remove it to save settings again.",
+                    config_path.display(),
+                    e
+                );
+                Config {
+                    read_only: true,
+                    ..Config::default()
+                }
+            }
+        };
+        config.env_api_key = std::env::var(API_KEY_ENV)
+            .ok()
+            .filter(|key| !key.trim().is_empty());
+        if config.key_store == Some(KeyStore::Keyring) {
+            match keyring::get(None) {
+                Ok(key) => config.keyring_api_key = key,
+                Err(e) => eprintln!("Could not read API key from keyring: {}", e),
+            }
+        }
+        config.load_profile_key();
+        config.config_path = config_path;
+        Ok(config)
+    }
+
+    /// The config file in use, whether or not it has been written yet
+    pub fn path(&self) -> &std::path::Path {
+        &self.config_path
+    }
+
+    /// False until something has been saved, e.g. on first run
+    pub fn exists(&self) -> bool {
+        self.config_path.exists()
+    }
+
+    /// Stop writing changes to disk, e.g. for --dry-run
+    pub fn set_read_only(&mut self) {
+        self.read_only = true;
+    }
+
+    fn save(&self) -> io::Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        // Create directory if it doesn't exist
+        if let Some(parent) = self.config_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let config_str = serde_json::to_string(self)?;
+        fs::write(&self.config_path, config_str)
+    }
+
+    fn profile_mut(&mut self) -> Option<&mut Profile> {
+        let name = self.active_profile.as_ref()?;
+        self.profiles.get_mut(name)
+    }
+
+    fn profile(&self) -> Option<&Profile> {
+        self.profiles.get(self.active_profile.as_ref()?)
+    }
+
+    // Read the active profile's key from the keyring, if that's where keys are kept
+    fn load_profile_key(&mut self) {
+        self.profile_keyring_api_key = None;
+        let Some(name) = self.profile().and(self.active_profile.clone()) else {
+            return;
+        };
+        if self.key_store == Some(KeyStore::Keyring) {
+            match keyring::get(Some(&name)) {
+                Ok(key) => self.profile_keyring_api_key = key,
+                Err(e) => eprintln!(
+                    "Could not read the API key for profile '{}' from keyring: {}",
+                    name, e
+                ),
+            }
+        }
+    }
+
+    // Put a profile's key in the keyring if that's the key store. Hands the key back when
+    // it belongs in the config file instead.
+    fn store_profile_key(&mut self, name: &str, key: String) -> Option<String> {
+        if self.key_store != Some(KeyStore::Keyring) {
+            return Some(key);
+        }
+        match keyring::set(Some(name), &key) {
+            Ok(()) => {
+                if self.active_profile.as_deref() == Some(name) {
+                    self.profile_keyring_api_key = Some(key);
+                }
+                None
+            }
+            Err(e) => {
+                eprintln!("{}; saving the key to the config file instead.", e);
+                Some(key)
+            }
+        }
+    }
+
+    pub fn set_key(&mut self, key: String) -> io::Result<()> {
+        if let Some(name) = self.profile().and(self.active_profile.clone()) {
+            let key = self.store_profile_key(&name, key);
+            if let Some(profile) = self.profile_mut() {
+                profile.api_key = key;
+            }
+            return self.save();
+        }
+        if self.key_store == Some(KeyStore::Keyring) {
+            match keyring::set(None, &key) {
+                Ok(()) => {
+                    self.keyring_api_key = Some(key);
+                    self.api_key = None;
+                    return self.save();
+                }
+                Err(e) => eprintln!("{}; saving the key to the config file instead.", e),
+            }
+        }
+        self.api_key = Some(key);
+        self.save()
+    }
+
+    /// Switch where the API key lives, moving an already stored key along with it
+    pub fn set_key_store(&mut self, store: KeyStore) -> io::Result<()> {
+        match store {
+            KeyStore::Keyring => {
+                if !keyring::is_available() {
+                    eprintln!("No OS keyring is available; keeping the key in the config file.");
+                    return Ok(());
+                }
+                if let Some(key) = &self.api_key {
+                    keyring::set(None, key)?;
+                    self.keyring_api_key = self.api_key.take();
+                    println!("Moved the stored API key into the OS keyring.");
+                }
+                for (name, profile) in &mut self.profiles {
+                    if let Some(key) = &profile.api_key {
+                        keyring::set(Some(name), key)?;
+                        profile.api_key = None;
+                        println!(
+                            "Moved the API key for profile '{}' into the OS keyring.",
+                            name
+                        );
+                    }
+                }
+            }
+            KeyStore::File => {
+                if self.api_key.is_none() {
+                    self.api_key = self.keyring_api_key.clone();
+                }
+                if self.key_store == Some(KeyStore::Keyring) {
+                    for (name, profile) in &mut self.profiles {
+                        if profile.api_key.is_none() {
+                            match keyring::get(Some(name)) {
+                                Ok(key) => profile.api_key = key,
+                                Err(e) => eprintln!(
+                                    "Could not read the API key for profile '{}' from keyring: {}",
+                                    name, e
+                                ),
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        self.key_store = Some(store);
+        self.load_profile_key();
+        self.save()
+    }
+
+    pub fn set_model(&mut self, model: String) -> io::Result<()> {
+        match self.profile_mut() {
+            Some(profile) => profile.model = Some(model),
+            None => self.model = Some(model),
+        }
+        self.save()
+    }
+
+    pub fn set_max_tokens(&mut self, max_tokens: u32) -> io::Result<()> {
+        match self.profile_mut() {
+            Some(profile) => profile.max_tokens = Some(max_tokens),
+            None => self.max_tokens = Some(max_tokens),
+        }
+        self.save()
+    }
+
+    pub fn set_system(&mut self, system: Option<String>) -> io::Result<()> {
+        match self.profile_mut() {
+            Some(profile) => profile.system = system,
+            None => self.system = system,
+        }
+        self.save()
+    }
+
+    pub fn set_temperature(&mut self, temperature: f32) -> io::Result<()> {
+        self.temperature = Some(temperature);
+        self.save()
+    }
+
+    pub fn set_top_p(&mut self, top_p: f32) -> io::Result<()> {
+        self.top_p = Some(top_p);
+        self.save()
+    }
+
+    /// History for the active session
+    pub fn history_path(&self) -> std::path::PathBuf {
+        match &self.active_session {
+            Some(name) => self.session_path(name),
+            None => self.default_history_path(),
+        }
+    }
+
+    pub fn default_history_path(&self) -> std::path::PathBuf {
+        self.config_path.with_file_name("history.json")
+    }
+
+    fn sessions_dir(&self) -> std::path::PathBuf {
+        self.config_path.with_file_name("sessions")
+    }
+
+    pub fn session_path(&self, name: &str) -> std::path::PathBuf {
+        self.sessions_dir().join(format!("{}.json", name))
+    }
+
+    /// Names of all saved sessions, sorted
+    pub fn sessions(&self) -> io::Result<Vec<String>> {
+        let entries = match fs::read_dir(self.sessions_dir()) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut names = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                if let Some(stem) = path.file_stem() {
+                    names.push(stem.to_string_lossy().into_owned());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Start a new, empty session and make it active
+    pub fn new_session(&mut self, name: &str) -> io::Result<()> {
+        validate_session_name(name)?;
+        let path = self.session_path(name);
+        if path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("A session named '{}' already exists.", name),
+            ));
+        }
+        save_history(&path, &[])?;
+        self.active_session = Some(name.to_string());
+        self.save()
+    }
+
+    /// Copy a conversation into a new session, leaving the active one as it is
+    pub fn fork_session(&self, name: &str, messages: &[ClaudeMessage]) -> io::Result<()> {
+        validate_session_name(name)?;
+        let path = self.session_path(name);
+        if path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("A session named '{}' already exists.", name),
+            ));
+        }
+        save_history(&path, messages)
+    }
+
+    /// Make a session active; "default" goes back to the unnamed history
+    pub fn switch_session(&mut self, name: &str) -> io::Result<()> {
+        if name == DEFAULT_SESSION {
+            self.active_session = None;
+            return self.save();
+        }
+        self.existing_session_path(name)?;
+        self.active_session = Some(name.to_string());
+        self.save()
+    }
+
+    /// The history file of a session that has been created, "default" included
+    pub fn existing_session_path(&self, name: &str) -> io::Result<std::path::PathBuf> {
+        if name == DEFAULT_SESSION {
+            return Ok(self.default_history_path());
+        }
+        validate_session_name(name)?;
+        let path = self.session_path(name);
+        if !path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "No session named '{}'. See `claude-cli session list`.",
+                    name
+                ),
+            ));
+        }
+        Ok(path)
+    }
+
+    /// Returns false if there was no such session
+    pub fn delete_session(&mut self, name: &str) -> io::Result<bool> {
+        validate_session_name(name)?;
+        match fs::remove_file(self.session_path(name)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        }
+        if self.active_session.as_deref() == Some(name) {
+            self.active_session = None;
+            self.save()?;
+        }
+        Ok(true)
+    }
+
+    pub fn set_cache(&mut self, enabled: bool) -> io::Result<()> {
+        self.cache = Some(enabled);
+        self.save()
+    }
+
+    /// Replies saved by --cache
+    pub fn cache_dir(&self) -> std::path::PathBuf {
+        self.config_path.with_file_name("cache")
+    }
+
+    // Previously typed prompts, for up/down recall at the input line
+    pub fn line_history_path(&self) -> std::path::PathBuf {
+        self.config_path.with_file_name("line_history")
+    }
+
+    /// The key to use for requests: the environment variable first, then the active
+    /// profile, then the keyring, then the config file
+    pub fn api_key(&self) -> Option<&str> {
+        self.env_api_key
+            .as_deref()
+            .or(self.profile_key())
+            .or(self.keyring_api_key.as_deref())
+            .or(self.api_key.as_deref())
+    }
+
+    // The active profile's own key, from the config file or the keyring
+    fn profile_key(&self) -> Option<&str> {
+        self.profile()?
+            .api_key
+            .as_deref()
+            .or(self.profile_keyring_api_key.as_deref())
+    }
+
+    /// Whether a profile has a key of its own rather than using the top-level one
+    pub fn profile_has_key(&self, name: &str) -> bool {
+        match self.profiles.get(name) {
+            Some(profile) if profile.api_key.is_some() => true,
+            Some(_) => {
+                self.key_store == Some(KeyStore::Keyring)
+                    && matches!(keyring::get(Some(name)), Ok(Some(_)))
+            }
+            None => false,
+        }
+    }
+
+    /// Which layer `api_key` took the key from
+    pub fn api_key_source(&self) -> Option<Source> {
+        if self.env_api_key.is_some() {
+            Some(Source::Env)
+        } else if self.profile_key().is_some() {
+            Some(Source::Profile)
+        } else if self.keyring_api_key.is_some() {
+            Some(Source::Keyring)
+        } else {
+            self.api_key.as_ref().map(|_| Source::File)
+        }
+    }
+
+    /// Which layer `model`, `max_tokens` and `system` read from: the active profile, the
+    /// top-level config, or neither
+    fn layered_source(&self, in_profile: fn(&Profile) -> bool, in_file: bool) -> Option<Source> {
+        if self.profile().is_some_and(in_profile) {
+            Some(Source::Profile)
+        } else {
+            in_file.then_some(Source::File)
+        }
+    }
+
+    pub fn model_source(&self) -> Option<Source> {
+        self.layered_source(|profile| profile.model.is_some(), self.model.is_some())
+    }
+
+    pub fn max_tokens_source(&self) -> Option<Source> {
+        self.layered_source(
+            |profile| profile.max_tokens.is_some(),
+            self.max_tokens.is_some(),
+        )
+    }
+
+    pub fn system_source(&self) -> Option<Source> {
+        self.layered_source(|profile| profile.system.is_some(), self.system.is_some())
+    }
+
+    /// The key store chosen in the config file, if any
+    pub fn key_store(&self) -> Option<KeyStore> {
+        self.key_store
+    }
+
+    pub fn model(&self) -> Option<&str> {
+        self.profile()
+            .and_then(|profile| profile.model.as_deref())
+            .or(self.model.as_deref())
+    }
+
+    pub fn max_tokens(&self) -> Option<u32> {
+        self.profile()
+            .and_then(|profile| profile.max_tokens)
+            .or(self.max_tokens)
+    }
+
+    pub fn system(&self) -> Option<&str> {
+        self.profile()
+            .and_then(|profile| profile.system.as_deref())
+            .or(self.system.as_deref())
+    }
+
+    pub fn add_profile(&mut self, name: String, mut profile: Profile) -> io::Result<()> {
+        // A replaced profile takes its key along with it
+        if self.profiles.contains_key(&name) {
+            self.forget_profile_key(&name);
+        }
+        if let Some(key) = profile.api_key.take() {
+            profile.api_key = self.store_profile_key(&name, key);
+        }
+        self.profiles.insert(name, profile);
+        self.save()
+    }
+
+    /// Returns false if there was no such profile
+    pub fn remove_profile(&mut self, name: &str) -> io::Result<bool> {
+        if self.profiles.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.forget_profile_key(name);
+        if self.active_profile.as_deref() == Some(name) {
+            self.active_profile = None;
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    fn forget_profile_key(&mut self, name: &str) {
+        if self.active_profile.as_deref() == Some(name) {
+            self.profile_keyring_api_key = None;
+        }
+        if self.key_store == Some(KeyStore::Keyring) {
+            if let Err(e) = keyring::delete(name) {
+                eprintln!(
+                    "Could not remove the API key for profile '{}' from keyring: {}",
+                    name, e
+                );
+            }
+        }
+    }
+
+    pub fn add_persona(&mut self, name: String, prompt: String) -> io::Result<()> {
+        self.personas.insert(name, prompt);
+        self.save()
+    }
+
+    /// Returns false if there was no such persona
+    pub fn remove_persona(&mut self, name: &str) -> io::Result<bool> {
+        if self.personas.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// The system prompt stored under a persona's name
+    pub fn persona(&self, name: &str) -> io::Result<&str> {
+        self.personas.get(name).map(String::as_str).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "No persona named '{}'. See `claude-cli persona list`.",
+                    name
+                ),
+            )
+        })
+    }
+
+    /// Create or replace a snippet. Names are single words so `:name` can find them.
+    pub fn add_snippet(&mut self, name: String, text: String) -> io::Result<()> {
+        if name.is_empty() || name.contains(char::is_whitespace) || name.starts_with(':') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Invalid snippet name '{}': use a single word, as in `:name`.",
+                    name
+                ),
+            ));
+        }
+        self.snippets.insert(name, text);
+        self.save()
+    }
+
+    /// Returns false if there was no such snippet
+    pub fn remove_snippet(&mut self, name: &str) -> io::Result<bool> {
+        if self.snippets.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    pub fn snippet(&self, name: &str) -> io::Result<&str> {
+        self.snippets.get(name).map(String::as_str).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "No snippet named '{}'. See `claude-cli snippet list`.",
+                    name
+                ),
+            )
+        })
+    }
+
+    /// Make a profile active for this and future sessions
+    pub fn use_profile(&mut self, name: &str) -> io::Result<()> {
+        if !self.profiles.contains_key(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "No profile named '{}'. See `claude-cli profile list`.",
+                    name
+                ),
+            ));
+        }
+        self.active_profile = Some(name.to_string());
+        self.load_profile_key();
+        self.save()
+    }
+}
+
+// `dirs` only reads XDG_CONFIG_HOME on Linux; people who set it elsewhere, e.g. on macOS,
+// expect it honored too. Relative values are ignored, as the XDG spec asks.
+fn config_dir() -> io::Result<std::path::PathBuf> {
+    #[cfg(unix)]
+    if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME")
+        .map(std::path::PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+    {
+        return Ok(dir);
+    }
+    dirs::config_dir()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Could not find config directory"))
+}
+
+/// The config file for `--portable`: beside the executable, so all state travels with it
+pub fn portable_path() -> io::Result<std::path::PathBuf> {
+    let exe = std::env::current_exe()?;
+    let dir = exe.parent().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "Could not find the program's directory",
+        )
+    })?;
+    Ok(dir.join("claude-cli/config.json"))
+}
+
+/// What `session list` calls the unnamed history.json
+pub const DEFAULT_SESSION: &str = "default";
+
+// A config file that can't be parsed is moved to config.json.bak, so the fresh one that
+// replaces it doesn't take the key and settings with it. If it can't be moved, nothing is saved
+// over it.
+fn set_aside(path: &std::path::Path, error: &serde_json::Error) -> Config {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    let backup = std::path::PathBuf::from(backup);
+    eprintln!(
+        "Warning: {} is not a valid config file ({}).",
+        path.display(),
+        error
+    );
+    match fs::rename(path, &backup) {
+        Ok(()) => {
+            eprintln!(
+                "It was moved to {} and a new, empty config is in use. Your API key and \
+                 settings can be copied back from the backup.",
+                backup.display()
+            );
+            Config::default()
+        }
+        Err(e) => {
+            eprintln!(
+                "It could not be backed up ({}), so changes won't be saved over it; fix or \
+                 remove it to save settings again.",
+                e
+            );
+            Config {
+                read_only: true,
+                ..Config::default()
+            }
+        }
+    }
+}
+
+// Session names become file names, so keep them to a safe character set
+fn validate_session_name(name: &str) -> io::Result<()> {
+    let valid = !name.is_empty()
+        && name != DEFAULT_SESSION
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Invalid session name '{}': use letters, digits, '-' and '_' (and not \"{}\").",
+                name, DEFAULT_SESSION
+            ),
+        ))
+    }
+}
+
+pub fn load_history(path: &std::path::Path) -> io::Result<Vec<ClaudeMessage>> {
+    let history_str = match fs::read_to_string(path) {
+        Ok(history_str) => history_str,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut messages: Vec<ClaudeMessage> = serde_json::from_str(&history_str)?;
+    // A trailing unanswered user turn would break alternation with the next message
+    if messages
+        .last()
+        .is_some_and(|message| message.role == "user")
+    {
+        messages.pop();
+    }
+    Ok(messages)
+}
+
+pub fn save_history(path: &std::path::Path, messages: &[ClaudeMessage]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let history_str = serde_json::to_string_pretty(messages)?;
+    fs::write(path, history_str)
+}
@@ -0,0 +1,151 @@
+// Project-local settings from a `.env` file: `NAME=value` lines, optionally after `export`,
+// with `#` comments and single- or double-quoted values. Variables already set in the real
+// environment win over the file.
+//
+// A file named with --env-file may set anything. One that is only found in the current
+// directory may set just the API key and the request settings: any directory could hold
+// one, and it shouldn't get to pick the pager, the editor or the config file (and with it
+// the server that receives the stored key).
+use crate::config::API_KEY_ENV;
+use crate::settings::{
+    MAX_TOKENS_ENV, MODEL_ENV, SYSTEM_ENV, TEMPERATURE_ENV, TOP_P_ENV, USER_ID_ENV,
+};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The file looked for in the current directory when no --env-file is given
+pub const DEFAULT_ENV_FILE: &str = ".env";
+
+// What a .env in the current directory may set
+const IMPLICIT: &[&str] = &[
+    API_KEY_ENV,
+    MODEL_ENV,
+    MAX_TOKENS_ENV,
+    SYSTEM_ENV,
+    TEMPERATURE_ENV,
+    TOP_P_ENV,
+    USER_ID_ENV,
+];
+
+/// Set every variable from the file that isn't set already
+pub fn load(path: &Path) -> io::Result<()> {
+    apply(parse(&fs::read_to_string(path)?)?);
+    Ok(())
+}
+
+/// Like `load` for the .env in the current directory, which may only set the API key and
+/// request settings. Returns the names of the variables left out.
+pub fn load_implicit(path: &Path) -> io::Result<Vec<String>> {
+    let (allowed, ignored): (Vec<_>, Vec<_>) = parse(&fs::read_to_string(path)?)?
+        .into_iter()
+        .partition(|(name, _)| IMPLICIT.contains(&name.as_str()));
+    apply(allowed);
+    Ok(ignored.into_iter().map(|(name, _)| name).collect())
+}
+
+fn apply(variables: Vec<(String, String)>) {
+    for (name, value) in variables {
+        if std::env::var_os(&name).is_none() {
+            std::env::set_var(name, value);
+        }
+    }
+}
+
+fn parse(contents: &str) -> io::Result<Vec<(String, String)>> {
+    let mut variables = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let invalid = |reason: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {}", number + 1, reason),
+            )
+        };
+        let (name, value) = line
+            .split_once('=')
+            .ok_or_else(|| invalid("expected NAME=value"))?;
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(invalid("names are letters, digits and '_'"));
+        }
+        variables.push((name.to_string(), unquote(value.trim()).map_err(invalid)?));
+    }
+    Ok(variables)
+}
+
+// Single quotes keep everything as written; double quotes understand \n, \t, \" and \\.
+// Unquoted values end at a ` #` comment.
+fn unquote(value: &str) -> Result<String, &'static str> {
+    if let Some(rest) = value.strip_prefix('\'') {
+        let end = rest.find('\'').ok_or("unterminated single quote")?;
+        return Ok(rest[..end].to_string());
+    }
+    if let Some(rest) = value.strip_prefix('"') {
+        let mut unquoted = String::new();
+        let mut chars = rest.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => return Ok(unquoted),
+                '\\' => match chars.next() {
+                    Some('n') => unquoted.push('\n'),
+                    Some('t') => unquoted.push('\t'),
+                    Some(other) => unquoted.push(other),
+                    None => break,
+                },
+                c => unquoted.push(c),
+            }
+        }
+        return Err("unterminated double quote");
+    }
+    let value = match value.find(" #") {
+        Some(comment) => &value[..comment],
+        None => value,
+    };
+    Ok(value.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unquotes_values() {
+        let cases = [
+            ("plain", Ok("plain")),
+            ("plain # comment", Ok("plain")),
+            ("a#b", Ok("a#b")),
+            ("'single # kept \\n'", Ok("single # kept \\n")),
+            ("\"tab\\there\\n\\\"q\\\"\"", Ok("tab\there\n\"q\"")),
+            ("\"trailing\" ignored", Ok("trailing")),
+            ("'open", Err("unterminated single quote")),
+            ("\"open", Err("unterminated double quote")),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(
+                unquote(value).as_deref().map_err(|e| *e),
+                expected,
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn parses_lines() {
+        let variables = parse("# comment\n\nexport A=1\nB = 'two'\n").unwrap();
+        assert_eq!(
+            variables,
+            vec![
+                ("A".to_string(), "1".to_string()),
+                ("B".to_string(), "two".to_string())
+            ]
+        );
+        assert!(parse("NO_EQUALS").is_err());
+        assert!(parse("BAD-NAME=1").is_err());
+    }
+}
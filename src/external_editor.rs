@@ -0,0 +1,48 @@
+// Composing a message in the user's own editor, the way `git commit` does
+use std::fs;
+use std::io;
+use std::process::Command;
+
+fn editor_command() -> String {
+    std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| {
+            if cfg!(windows) {
+                "notepad".to_string()
+            } else {
+                "vi".to_string()
+            }
+        })
+}
+
+/// Open `initial` in the editor and return what was saved, or None if the result is empty
+/// or unchanged
+pub fn compose(initial: &str) -> io::Result<Option<String>> {
+    let path = std::env::temp_dir().join(format!("claude-cli-message-{}.md", std::process::id()));
+    fs::write(&path, initial)?;
+
+    // EDITOR may carry arguments, e.g. "code --wait"
+    let command = editor_command();
+    let mut parts = command.split_whitespace();
+    let program = parts.next().unwrap_or("vi");
+    let status = Command::new(program).args(parts).arg(&path).status();
+
+    let contents = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+
+    let status = status.map_err(|e| io::Error::new(e.kind(), format!("{}: {}", program, e)))?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "{} exited with {}",
+            program, status
+        )));
+    }
+    let contents = contents?;
+    let text = contents.trim_end();
+    if text.trim().is_empty() || text == initial.trim_end() {
+        return Ok(None);
+    }
+    Ok(Some(text.to_string()))
+}
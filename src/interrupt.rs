@@ -0,0 +1,49 @@
+// Ctrl-C in chat: it cancels the request in flight, and with none in flight it ends the
+// program as it would without a handler. tokio's handler stays for the life of the process
+// once installed, so a single task owns it and decides which of the two applies. The
+// prompt reads keys in raw mode, where Ctrl-C is a key rather than a signal, so a signal
+// arriving then was sent from elsewhere, and it ends the program just as it always would.
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+// What a shell reports for a program ended by SIGINT
+const EXIT_INTERRUPTED: i32 = 130;
+
+pub struct Interrupts {
+    cancel: Arc<Notify>,
+    busy: Arc<AtomicBool>,
+}
+
+impl Interrupts {
+    pub fn install() -> Self {
+        let cancel = Arc::new(Notify::new());
+        let busy = Arc::new(AtomicBool::new(false));
+        let (task_cancel, task_busy) = (cancel.clone(), busy.clone());
+        tokio::spawn(async move {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                if task_busy.load(Ordering::SeqCst) {
+                    task_cancel.notify_waiters();
+                } else {
+                    eprintln!();
+                    std::process::exit(EXIT_INTERRUPTED);
+                }
+            }
+        });
+        Interrupts { cancel, busy }
+    }
+
+    /// Run `future` to the end, or None if Ctrl-C comes first
+    pub async fn cancellable<F: Future>(&self, future: F) -> Option<F::Output> {
+        // Registered before the flag goes up, so no interrupt falls between the two
+        let cancelled = self.cancel.notified();
+        self.busy.store(true, Ordering::SeqCst);
+        let result = tokio::select! {
+            result = future => Some(result),
+            _ = cancelled => None,
+        };
+        self.busy.store(false, Ordering::SeqCst);
+        result
+    }
+}
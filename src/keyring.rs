@@ -0,0 +1,164 @@
+// Stores the API key in the OS keychain by driving the platform's own tool:
+// `security` on macOS and `secret-tool` (libsecret / Secret Service) on Linux.
+// Other platforms report the keyring as unavailable so callers fall back to the file.
+// Each profile's key is a separate entry, under an account named after the profile.
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+const SERVICE: &str = "claude-cli";
+const ACCOUNT: &str = "api_key";
+
+// The entry for a profile's key, or the top-level one
+fn account(profile: Option<&str>) -> String {
+    match profile {
+        Some(name) => format!("{}:{}", ACCOUNT, name),
+        None => ACCOUNT.to_string(),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyStore {
+    /// Plaintext in config.json
+    File,
+    /// The OS keychain
+    Keyring,
+}
+
+fn unavailable() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "No OS keyring is available on this system",
+    )
+}
+
+// Run a command, feeding `input` on stdin so secrets never appear in the process list
+fn run(command: &mut Command, input: Option<&str>) -> io::Result<std::process::Output> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => unavailable(),
+            _ => e,
+        })?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input.as_bytes())?;
+    }
+    child.wait_with_output()
+}
+
+fn output_to_key(output: std::process::Output) -> Option<String> {
+    if !output.status.success() {
+        return None;
+    }
+    let key = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if key.is_empty() {
+        None
+    } else {
+        Some(key)
+    }
+}
+
+/// Whether a supported keyring tool can be run on this machine
+pub fn is_available() -> bool {
+    if cfg!(target_os = "macos") {
+        run(Command::new("security").arg("help"), None).is_ok()
+    } else if cfg!(target_os = "linux") {
+        run(Command::new("secret-tool").arg("--help"), None).is_ok()
+    } else {
+        false
+    }
+}
+
+/// Read the stored key, or the profile's, `Ok(None)` if nothing has been stored yet
+pub fn get(profile: Option<&str>) -> io::Result<Option<String>> {
+    let account = account(profile);
+    if cfg!(target_os = "macos") {
+        let output = run(
+            Command::new("security").args([
+                "find-generic-password",
+                "-s",
+                SERVICE,
+                "-a",
+                &account,
+                "-w",
+            ]),
+            None,
+        )?;
+        Ok(output_to_key(output))
+    } else if cfg!(target_os = "linux") {
+        let output = run(
+            Command::new("secret-tool").args(["lookup", "service", SERVICE, "account", &account]),
+            None,
+        )?;
+        Ok(output_to_key(output))
+    } else {
+        Err(unavailable())
+    }
+}
+
+/// Store (or replace) the key, or the profile's
+pub fn set(profile: Option<&str>, key: &str) -> io::Result<()> {
+    let account = account(profile);
+    let output = if cfg!(target_os = "macos") {
+        // `security -i` reads commands from stdin, keeping the key out of argv
+        let command = format!(
+            "add-generic-password -U -s {} -a {} -w \"{}\"\n",
+            SERVICE,
+            account,
+            key.replace('\\', "\\\\").replace('"', "\\\"")
+        );
+        run(Command::new("security").arg("-i"), Some(&command))?
+    } else if cfg!(target_os = "linux") {
+        run(
+            Command::new("secret-tool").args([
+                "store",
+                "--label=Claude CLI API key",
+                "service",
+                SERVICE,
+                "account",
+                &account,
+            ]),
+            Some(key),
+        )?
+    } else {
+        return Err(unavailable());
+    };
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "Keyring refused to store the key: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+/// Forget a profile's key, e.g. when the profile is removed; nothing stored is fine
+pub fn delete(profile: &str) -> io::Result<()> {
+    let account = account(Some(profile));
+    if cfg!(target_os = "macos") {
+        run(
+            Command::new("security").args([
+                "delete-generic-password",
+                "-s",
+                SERVICE,
+                "-a",
+                &account,
+            ]),
+            None,
+        )?;
+    } else if cfg!(target_os = "linux") {
+        run(
+            Command::new("secret-tool").args(["clear", "service", SERVICE, "account", &account]),
+            None,
+        )?;
+    } else {
+        return Err(unavailable());
+    }
+    Ok(())
+}
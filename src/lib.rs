@@ -0,0 +1,24 @@
+//! The library behind `claude-cli`: the Messages API request and response types, and a
+//! [`Client`] to send conversations with, either waiting for the whole reply or streaming it.
+//!
+//! ```no_run
+//! use anthropic_in_a_shell::{ClaudeMessage, Client, Settings};
+//!
+//! # async fn run() -> Result<(), anthropic_in_a_shell::AppError> {
+//! let client = Client::new(std::env::var("ANTHROPIC_API_KEY").unwrap()).with_settings(Settings {
+//!     system: Some("Answer in one sentence.".to_string()),
+//!     ..Settings::default()
+//! });
+//! let reply = client
+//!     .send_streaming(&[ClaudeMessage::user("Why is the sky blue?")], |text| print!("{}", text))
+//!     .await?;
+//! println!("\n({} tokens out)", reply.output_tokens);
+//! # Ok(())
+//! # }
+//! ```
+pub mod client;
+pub mod modules;
+pub mod throttle;
+
+pub use client::{Client, Reply, Settings};
+pub use modules::{AppError, ClaudeMessage, MessageContent};
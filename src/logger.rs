@@ -0,0 +1,197 @@
+// Diagnostic logging to stderr for `--verbose` and `RUST_LOG`, in the spirit of env_logger.
+// Every message passes through redaction, so registered secrets never reach the log.
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::io::Write;
+use std::sync::{Mutex, OnceLock};
+
+const REDACTED: &str = "[REDACTED]";
+
+struct Logger {
+    // (target prefix, level) pairs from RUST_LOG; an empty prefix matches everything
+    directives: Vec<(String, LevelFilter)>,
+    secrets: Mutex<Vec<String>>,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Install the logger. `verbose` turns on debug output for this program regardless of
+/// RUST_LOG, which otherwise works as with env_logger, e.g. `RUST_LOG=debug` or
+/// `RUST_LOG=reqwest=trace,anthropic_in_a_shell=debug`.
+pub fn init(verbose: bool) {
+    let mut directives = std::env::var("RUST_LOG")
+        .map(|spec| parse_directives(&spec))
+        .unwrap_or_default();
+    if verbose {
+        directives.push((env!("CARGO_CRATE_NAME").to_string(), LevelFilter::Debug));
+    }
+    let max_level = directives
+        .iter()
+        .map(|(_, level)| *level)
+        .max()
+        .unwrap_or(LevelFilter::Off);
+    let logger = LOGGER.get_or_init(|| Logger {
+        directives,
+        secrets: Mutex::new(Vec::new()),
+    });
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+/// Never let `secret` appear in the log, whole or in part
+pub fn redact(secret: &str) {
+    if secret.is_empty() {
+        return;
+    }
+    if let Some(logger) = LOGGER.get() {
+        if let Ok(mut secrets) = logger.secrets.lock() {
+            secrets.push(secret.to_string());
+        }
+    }
+}
+
+fn parse_directives(spec: &str) -> Vec<(String, LevelFilter)> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .filter_map(|directive| match directive.split_once('=') {
+            Some((target, level)) => Some((target.to_string(), level.parse().ok()?)),
+            // A bare word is either a level for everything or a target to enable fully
+            None => match directive.parse() {
+                Ok(level) => Some((String::new(), level)),
+                Err(_) => Some((directive.to_string(), LevelFilter::Trace)),
+            },
+        })
+        .collect()
+}
+
+impl Logger {
+    // The most specific directive wins, as with env_logger
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.directives
+            .iter()
+            .filter(|(prefix, _)| target.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(LevelFilter::Off)
+    }
+
+    fn scrub(&self, mut message: String) -> String {
+        let Ok(secrets) = self.secrets.lock() else {
+            return REDACTED.to_string();
+        };
+        for secret in secrets.iter() {
+            message = scrub_secret(&message, secret);
+        }
+        message
+    }
+}
+
+// Removes the secret itself, and any copy of its start or end that runs on as the secret
+// does, so a truncated or wrapped copy is caught too. One pass over the message, which may
+// be a whole request body.
+fn scrub_secret(message: &str, secret: &str) -> String {
+    const MIN_FRAGMENT: usize = 8;
+    let message = message.replace(secret, REDACTED);
+    let secret: Vec<char> = secret.chars().collect();
+    if secret.len() <= MIN_FRAGMENT {
+        return message;
+    }
+    let head = &secret[..MIN_FRAGMENT];
+    let tail = &secret[secret.len() - MIN_FRAGMENT..];
+    let chars: Vec<char> = message.chars().collect();
+    let mut hidden = vec![false; chars.len()];
+    for at in 0..chars.len() {
+        if chars[at..].starts_with(head) {
+            let length = chars[at..]
+                .iter()
+                .zip(&secret)
+                .take_while(|(a, b)| a == b)
+                .count();
+            hidden[at..at + length].fill(true);
+        }
+        if chars[..=at].ends_with(tail) {
+            let length = chars[..=at]
+                .iter()
+                .rev()
+                .zip(secret.iter().rev())
+                .take_while(|(a, b)| a == b)
+                .count();
+            hidden[at + 1 - length..=at].fill(true);
+        }
+    }
+    let mut scrubbed = String::with_capacity(message.len());
+    for (index, &c) in chars.iter().enumerate() {
+        if !hidden[index] {
+            scrubbed.push(c);
+        } else if index == 0 || !hidden[index - 1] {
+            scrubbed.push_str(REDACTED);
+        }
+    }
+    scrubbed
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let message = self.scrub(record.args().to_string());
+        let label = match record.level() {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        };
+        let _ = writeln!(
+            std::io::stderr(),
+            "[{} {}] {}",
+            label,
+            record.target(),
+            message
+        );
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scrubs_whole_and_partial_secrets() {
+        let secret = "sk-ant-REDACTED";
+        let cases = [
+            (
+                "key sk-ant-REDACTED end",
+                "key [REDACTED] end",
+            ),
+            ("cut sk-ant-api03-abcd...", "cut [REDACTED]..."),
+            (
+                "wrapped sk-ant-api\n03-abcdefghijklmnop",
+                "wrapped [REDACTED]\n[REDACTED]",
+            ),
+            ("nothing sk-ant here", "nothing sk-ant here"),
+        ];
+        for (message, expected) in cases {
+            assert_eq!(scrub_secret(message, secret), expected, "{:?}", message);
+        }
+    }
+
+    #[test]
+    fn scrubbing_a_large_message_is_quick() {
+        let secret = "sk-ant-api03-".to_string() + &"x".repeat(90);
+        let message = "y".repeat(1_000_000) + &secret;
+        let started = std::time::Instant::now();
+        assert!(scrub_secret(&message, &secret).ends_with(REDACTED));
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,296 @@
+// Renders Claude's Markdown replies for the terminal with ANSI styles: headings, bold,
+// italics, inline code, bullet lists and fenced code blocks with light syntax highlighting.
+// Rendering is line based so it can keep up with a streamed reply.
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const ITALIC: &str = "\x1b[3m";
+const UNDERLINE: &str = "\x1b[4m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const MAGENTA: &str = "\x1b[35m";
+const CYAN: &str = "\x1b[36m";
+
+// Keywords shared by most languages Claude writes code in
+const KEYWORDS: &[&str] = &[
+    "as",
+    "async",
+    "await",
+    "break",
+    "case",
+    "class",
+    "const",
+    "continue",
+    "def",
+    "default",
+    "do",
+    "else",
+    "enum",
+    "export",
+    "extends",
+    "false",
+    "fn",
+    "for",
+    "from",
+    "func",
+    "function",
+    "if",
+    "impl",
+    "import",
+    "in",
+    "interface",
+    "let",
+    "loop",
+    "match",
+    "mod",
+    "mut",
+    "new",
+    "nil",
+    "None",
+    "null",
+    "package",
+    "pub",
+    "return",
+    "self",
+    "static",
+    "struct",
+    "switch",
+    "this",
+    "trait",
+    "true",
+    "True",
+    "False",
+    "try",
+    "catch",
+    "type",
+    "use",
+    "var",
+    "while",
+    "with",
+    "yield",
+];
+
+/// Incrementally renders streamed Markdown, emitting each line once it is complete
+pub struct MarkdownStream {
+    pending: String,
+    in_code_block: bool,
+}
+
+impl MarkdownStream {
+    pub fn new() -> Self {
+        MarkdownStream {
+            pending: String::new(),
+            in_code_block: false,
+        }
+    }
+
+    /// Feed more text, returning whatever complete lines are now ready to print
+    pub fn push(&mut self, text: &str) -> String {
+        self.pending.push_str(text);
+        let mut output = String::new();
+        while let Some(newline) = self.pending.find('\n') {
+            let line: String = self.pending.drain(..=newline).collect();
+            output.push_str(&self.render_line(line.trim_end_matches('\n')));
+            output.push('\n');
+        }
+        output
+    }
+
+    /// Render any trailing partial line at the end of the reply
+    pub fn finish(&mut self) -> String {
+        if self.pending.is_empty() {
+            return String::new();
+        }
+        let line = std::mem::take(&mut self.pending);
+        self.render_line(&line)
+    }
+
+    fn render_line(&mut self, line: &str) -> String {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") {
+            self.in_code_block = !self.in_code_block;
+            return format!("{}{}{}", DIM, line, RESET);
+        }
+        if self.in_code_block {
+            return highlight_code(line);
+        }
+        if let Some(heading) = heading_text(trimmed) {
+            return format!("{}{}{}{}", BOLD, UNDERLINE, render_inline(heading), RESET);
+        }
+        if let Some(item) = trimmed
+            .strip_prefix("- ")
+            .or_else(|| trimmed.strip_prefix("* "))
+        {
+            let indent = &line[..line.len() - trimmed.len()];
+            return format!("{}• {}", indent, render_inline(item));
+        }
+        if let Some(quote) = trimmed.strip_prefix("> ") {
+            return format!("{}│ {}{}", DIM, render_inline(quote), RESET);
+        }
+        render_inline(line)
+    }
+}
+
+/// Render a complete reply in one go
+pub fn render(text: &str) -> String {
+    let mut stream = MarkdownStream::new();
+    let mut output = stream.push(text);
+    output.push_str(&stream.finish());
+    output
+}
+
+fn heading_text(line: &str) -> Option<&str> {
+    let hashes = line.chars().take_while(|&c| c == '#').count();
+    if (1..=6).contains(&hashes) {
+        line[hashes..].strip_prefix(' ')
+    } else {
+        None
+    }
+}
+
+// **bold**, *italic* / _italic_ and `code` spans within a single line
+fn render_inline(line: &str) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let mut output = String::new();
+    let mut bold = false;
+    let mut italic = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '`' {
+            if let Some(end) = chars[i + 1..].iter().position(|&c| c == '`') {
+                let code: String = chars[i + 1..i + 1 + end].iter().collect();
+                output.push_str(&format!("{}{}{}", CYAN, code, RESET));
+                output.push_str(&restore(bold, italic));
+                i += end + 2;
+                continue;
+            }
+        }
+        if c == '*' && chars.get(i + 1) == Some(&'*') {
+            bold = !bold;
+            output.push_str(RESET);
+            output.push_str(&restore(bold, italic));
+            i += 2;
+            continue;
+        }
+        // A lone `*` or `_` toggles italics only at a word boundary, so snake_case survives
+        if (c == '*' || c == '_') && is_emphasis_marker(&chars, i, italic) {
+            italic = !italic;
+            output.push_str(RESET);
+            output.push_str(&restore(bold, italic));
+            i += 1;
+            continue;
+        }
+        output.push(c);
+        i += 1;
+    }
+    if bold || italic {
+        output.push_str(RESET);
+    }
+    output
+}
+
+fn is_emphasis_marker(chars: &[char], i: usize, closing: bool) -> bool {
+    let before = i.checked_sub(1).map(|j| chars[j]);
+    let after = chars.get(i + 1).copied();
+    if closing {
+        before.is_some_and(|c| !c.is_whitespace()) && after.is_none_or(|c| !c.is_alphanumeric())
+    } else {
+        before.is_none_or(|c| !c.is_alphanumeric())
+            && after.is_some_and(|c| !c.is_whitespace())
+            && chars[i + 1..].contains(&chars[i])
+    }
+}
+
+fn restore(bold: bool, italic: bool) -> String {
+    let mut style = String::new();
+    if bold {
+        style.push_str(BOLD);
+    }
+    if italic {
+        style.push_str(ITALIC);
+    }
+    style
+}
+
+// Colors keywords, string literals, numbers and line comments
+fn highlight_code(line: &str) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let mut output = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let rest: String = chars[i..].iter().collect();
+        if rest.starts_with("//") || (c == '#' && !rest.starts_with("#[")) {
+            output.push_str(&format!("{}{}{}", DIM, rest, RESET));
+            break;
+        }
+        // A `'` with no partner on the line is a lifetime or apostrophe, not a literal
+        if c == '"' || (c == '\'' && chars[i + 1..].contains(&'\'')) {
+            let mut end = i + 1;
+            while end < chars.len() && chars[end] != c {
+                if chars[end] == '\\' {
+                    end += 1;
+                }
+                end += 1;
+            }
+            let end = end.min(chars.len() - 1);
+            let literal: String = chars[i..=end].iter().collect();
+            output.push_str(&format!("{}{}{}", GREEN, literal, RESET));
+            i = end + 1;
+            continue;
+        }
+        if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            if KEYWORDS.contains(&word.as_str()) {
+                output.push_str(&format!("{}{}{}", MAGENTA, word, RESET));
+            } else {
+                output.push_str(&word);
+            }
+            continue;
+        }
+        if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                i += 1;
+            }
+            let number: String = chars[start..i].iter().collect();
+            output.push_str(&format!("{}{}{}", YELLOW, number, RESET));
+            continue;
+        }
+        output.push(c);
+        i += 1;
+    }
+    output
+}
+
+/// The contents of the first fenced code block in `text`, without the fences
+pub fn first_code_block(text: &str) -> Option<String> {
+    let mut lines = text.lines();
+    lines.find(|line| line.trim_start().starts_with("```"))?;
+    let code: Vec<&str> = lines
+        .take_while(|line| !line.trim_start().starts_with("```"))
+        .collect();
+    Some(code.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_the_first_code_block_only() {
+        let reply = "Try:\n```rust\nfn main() {\n}\n```\nor\n```\nsecond\n```";
+        assert_eq!(first_code_block(reply).unwrap(), "fn main() {\n}");
+        assert_eq!(
+            first_code_block("```\nunterminated").unwrap(),
+            "unterminated"
+        );
+        assert!(first_code_block("no code here").is_none());
+    }
+}
@@ -0,0 +1,44 @@
+// Models listed by `claude-cli models` when the API's own list can't be fetched. The list
+// can be replaced in config.json under "models".
+
+/// Model id and a short description, newest first
+pub const KNOWN_MODELS: &[(&str, &str)] = &[
+    (
+        "claude-opus-4-5",
+        "Most capable, for complex reasoning and coding; 200k context",
+    ),
+    (
+        "claude-sonnet-4-5",
+        "Balanced capability and speed; 200k context",
+    ),
+    ("claude-haiku-4-5", "Fastest current model; 200k context"),
+    ("claude-opus-4-1", "Previous generation Opus; 200k context"),
+    (
+        "claude-sonnet-4-0",
+        "Previous generation Sonnet; 200k context",
+    ),
+    (
+        "claude-3-7-sonnet-latest",
+        "Claude 3.7 Sonnet; 200k context",
+    ),
+    (
+        "claude-3-5-sonnet-20241022",
+        "Claude 3.5 Sonnet; 200k context",
+    ),
+    (
+        "claude-3-5-haiku-20241022",
+        "Fast and inexpensive; 200k context",
+    ),
+    (
+        "claude-3-haiku-20240307",
+        "Oldest and cheapest; 200k context",
+    ),
+];
+
+/// What we know about `id`, matching dated snapshots against their family
+pub fn describe(id: &str) -> Option<&'static str> {
+    KNOWN_MODELS
+        .iter()
+        .find(|(known, _)| id == *known || id.starts_with(known.trim_end_matches("-latest")))
+        .map(|(_, description)| *description)
+}
@@ -2,29 +2,290 @@
 #![allow(unused_variables)]
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct ClaudeMessage {
     pub role: String,
-    pub content: String,
+    pub content: MessageContent,
+    /// Labels from /tag, kept in history for `claude-cli search`; never sent to the API
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+impl ClaudeMessage {
+    pub fn user(content: impl Into<MessageContent>) -> Self {
+        ClaudeMessage {
+            role: "user".to_string(),
+            content: content.into(),
+            tags: Vec::new(),
+        }
+    }
+
+    pub fn assistant(content: impl Into<MessageContent>) -> Self {
+        ClaudeMessage {
+            role: "assistant".to_string(),
+            content: content.into(),
+            tags: Vec::new(),
+        }
+    }
+}
+
+/// A message body: a plain string, or an array of content blocks when it carries more than
+/// text. Untagged so both API shapes (and older history files) round-trip.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Blocks(Vec<InputContentBlock>),
+}
+
+impl MessageContent {
+    /// The text parts joined together, leaving out images
+    pub fn text(&self) -> String {
+        match self {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Blocks(blocks) => blocks
+                .iter()
+                .filter_map(|block| match block {
+                    InputContentBlock::Text { text, .. } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n\n"),
+        }
+    }
+
+    pub fn image_count(&self) -> usize {
+        match self {
+            MessageContent::Text(_) => 0,
+            MessageContent::Blocks(blocks) => blocks
+                .iter()
+                .filter(|block| matches!(block, InputContentBlock::Image { .. }))
+                .count(),
+        }
+    }
+
+    /// Whether this is a turn of tool results rather than something the user wrote
+    pub fn is_tool_result(&self) -> bool {
+        match self {
+            MessageContent::Text(_) => false,
+            MessageContent::Blocks(blocks) => blocks
+                .iter()
+                .any(|block| matches!(block, InputContentBlock::ToolResult { .. })),
+        }
+    }
+
+    /// The same content with Windows (`\r\n`) and stray `\r` line endings turned into `\n`
+    pub fn with_unix_newlines(self) -> MessageContent {
+        let normalize = |text: String| {
+            if text.contains('\r') {
+                text.replace("\r\n", "\n").replace('\r', "\n")
+            } else {
+                text
+            }
+        };
+        match self {
+            MessageContent::Text(text) => MessageContent::Text(normalize(text)),
+            MessageContent::Blocks(blocks) => MessageContent::Blocks(
+                blocks
+                    .into_iter()
+                    .map(|block| match block {
+                        InputContentBlock::Text {
+                            text,
+                            cache_control,
+                        } => InputContentBlock::Text {
+                            text: normalize(text),
+                            cache_control,
+                        },
+                        other => other,
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// The same content as blocks, with a cache breakpoint after the last one
+    pub fn cached(&self) -> MessageContent {
+        let mut blocks = match self {
+            MessageContent::Text(text) => vec![InputContentBlock::text(text.clone())],
+            MessageContent::Blocks(blocks) => blocks.clone(),
+        };
+        if let Some(last) = blocks.last_mut() {
+            last.set_cache_control();
+        }
+        MessageContent::Blocks(blocks)
+    }
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        MessageContent::Text(text)
+    }
+}
+
+impl From<&str> for MessageContent {
+    fn from(text: &str) -> Self {
+        MessageContent::Text(text.to_string())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InputContentBlock {
+    Text {
+        text: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<CacheControl>,
+    },
+    Image {
+        source: ImageSource,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<CacheControl>,
+    },
+    // A tool call from an assistant turn, kept in history so its result can follow
+    ToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        content: String,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        is_error: bool,
+    },
+    // Extended thinking from an assistant turn; the API wants it back, signature and all,
+    // ahead of the tool calls it led to
+    Thinking {
+        thinking: String,
+        signature: String,
+    },
+    RedactedThinking {
+        data: String,
+    },
+}
+
+impl InputContentBlock {
+    pub fn text(text: String) -> Self {
+        InputContentBlock::Text {
+            text,
+            cache_control: None,
+        }
+    }
+
+    /// Mark everything up to and including this block as cacheable
+    pub fn set_cache_control(&mut self) {
+        if let InputContentBlock::Text { cache_control, .. }
+        | InputContentBlock::Image { cache_control, .. } = self
+        {
+            *cache_control = Some(CacheControl::ephemeral());
+        }
+    }
+}
+
+/// A prompt caching breakpoint
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CacheControl {
+    #[serde(rename = "type")]
+    pub cache_type: String,
+}
+
+impl CacheControl {
+    pub fn ephemeral() -> Self {
+        CacheControl {
+            cache_type: "ephemeral".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ImageSource {
+    // Always "base64" for images read from disk
+    #[serde(rename = "type")]
+    pub source_type: String,
+    pub media_type: String,
+    pub data: String,
+}
+
+/// A local tool Claude may call, described by a JSON schema for its input
+#[derive(Serialize, Clone, Debug)]
+pub struct Tool {
+    pub name: String,
+    pub description: String,
+    pub input_schema: serde_json::Value,
 }
+
 #[derive(Serialize)]
 pub struct ClaudeApiRequest {
     pub model: String,
     pub max_tokens: u32,
     pub messages: Vec<ClaudeMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    // A plain string, or text blocks when the prompt carries a cache breakpoint
+    pub system: Option<MessageContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_sequences: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<Thinking>,
+    // Only sent when true so plain requests keep the original body shape
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stream: bool,
+}
+
+/// Request metadata; `user_id` is an opaque id for the person behind the request, which
+/// the API uses for abuse detection
+#[derive(Serialize, Debug, Clone)]
+pub struct Metadata {
+    pub user_id: String,
+}
+
+/// Extended thinking: Claude may reason for up to `budget_tokens` before it answers
+#[derive(Serialize, Debug, Clone)]
+pub struct Thinking {
+    #[serde(rename = "type")]
+    pub thinking_type: String,
+    pub budget_tokens: u32,
+}
+
+impl Thinking {
+    pub fn enabled(budget_tokens: u32) -> Self {
+        Thinking {
+            thinking_type: "enabled".to_string(),
+            budget_tokens,
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
 pub struct ClaudeContentItem {
-    text: String,
+    #[serde(default)]
+    pub text: String,
     #[serde(rename = "type")]
-    content_type: String,
+    pub content_type: String,
+    // Set on tool_use blocks
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub input: Option<serde_json::Value>,
+    // Set on thinking blocks, and `data` on redacted_thinking ones
+    #[serde(default)]
+    pub thinking: String,
+    pub signature: Option<String>,
+    pub data: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct ClaudeUsage {
     pub input_tokens: Option<u32>,
     pub output_tokens: Option<u32>,
+    pub cache_creation_input_tokens: Option<u32>,
+    pub cache_read_input_tokens: Option<u32>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -40,7 +301,19 @@ pub struct ClaudeApiResponse {
     pub usage: ClaudeUsage,
 }
 
+// GET /v1/models
 #[derive(Deserialize, Debug)]
+pub struct ModelList {
+    pub data: Vec<ModelEntry>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ModelEntry {
+    pub id: String,
+    pub display_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct ClaudeErrorDetails {
     #[serde(rename = "type")]
     pub error_type: String,
@@ -54,6 +327,120 @@ pub struct ClaudeApiError {
     pub error: ClaudeErrorDetails,
 }
 
+/// Why a request to the API failed, for callers that act on the kind of failure
+#[derive(Debug)]
+pub enum AppError {
+    /// The API answered with an error object, e.g. `authentication_error`. The request-id
+    /// header is what Anthropic support asks for.
+    Api {
+        error_type: String,
+        message: String,
+        request_id: Option<String>,
+    },
+    /// The API key was refused (HTTP 401 / `authentication_error`)
+    Auth,
+    /// No complete answer arrived: connection failures, dropped streams and timeouts
+    Network(String),
+    /// The response was neither a reply nor an API error
+    Deserialize {
+        status: u16,
+        message: String,
+        request_id: Option<String>,
+    },
+    /// The request could not be set up from the current settings
+    Config(String),
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AppError::Api {
+                error_type,
+                message,
+                request_id: Some(request_id),
+            } => write!(
+                f,
+                "{} (request-id: {}): {}",
+                error_type, request_id, message
+            ),
+            AppError::Api {
+                error_type,
+                message,
+                request_id: None,
+            } => write!(f, "{}: {}", error_type, message),
+            AppError::Auth => write!(
+                f,
+                "Your API key appears to be invalid or expired. \
+                 Run `claude-cli setkey <key>` to update it."
+            ),
+            AppError::Network(message) => write!(f, "{}", message),
+            AppError::Deserialize {
+                status,
+                message,
+                request_id: Some(request_id),
+            } => write!(
+                f,
+                "Unexpected response ({}, request-id: {}): {}",
+                status, request_id, message
+            ),
+            AppError::Deserialize {
+                status,
+                message,
+                request_id: None,
+            } => write!(f, "Unexpected response ({}): {}", status, message),
+            AppError::Config(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+// Exit codes, so scripts can tell kinds of failure apart
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_CONFIG: i32 = 2;
+pub const EXIT_NETWORK: i32 = 3;
+pub const EXIT_RATE_LIMITED: i32 = 4;
+pub const EXIT_USAGE: i32 = 5;
+
+impl AppError {
+    /// The same error, noting the request-id header of the response it came from
+    pub fn with_request_id(mut self, id: Option<&str>) -> Self {
+        if let AppError::Api { request_id, .. } | AppError::Deserialize { request_id, .. } =
+            &mut self
+        {
+            *request_id = id.map(str::to_string);
+        }
+        self
+    }
+
+    /// The exit code for a run that ends with this error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            AppError::Auth | AppError::Config(_) => EXIT_CONFIG,
+            AppError::Network(_) => EXIT_NETWORK,
+            AppError::Api { error_type, .. } => match error_type.as_str() {
+                "authentication_error" | "permission_error" => EXIT_CONFIG,
+                // Both mean "try again later"
+                "rate_limit_error" | "overloaded_error" => EXIT_RATE_LIMITED,
+                // Usually a flag the API didn't accept, such as an unknown --model
+                "invalid_request_error" | "not_found_error" => EXIT_USAGE,
+                _ => EXIT_FAILURE,
+            },
+            AppError::Deserialize { .. } => EXIT_FAILURE,
+        }
+    }
+}
+
+impl From<ClaudeErrorDetails> for AppError {
+    fn from(error: ClaudeErrorDetails) -> Self {
+        AppError::Api {
+            error_type: error.error_type,
+            message: error.message,
+            request_id: None,
+        }
+    }
+}
+
 // Add these to modules.rs
 
 #[derive(Deserialize, Debug)]
@@ -79,6 +466,8 @@ pub enum StreamEvent {
     MessageStop,
     #[serde(rename = "ping")]
     Ping,
+    #[serde(rename = "error")]
+    Error { error: ClaudeErrorDetails },
 }
 
 #[derive(Deserialize, Debug)]
@@ -98,14 +487,28 @@ pub struct MessageStart {
 pub struct ContentBlock {
     #[serde(rename = "type")]
     pub block_type: String,
+    #[serde(default)]
     pub text: String,
+    // Set on tool_use blocks, whose input then arrives as input_json_delta events
+    pub id: Option<String>,
+    pub name: Option<String>,
+    // Set on redacted_thinking blocks; thinking ones arrive as thinking_delta events
+    pub data: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct Delta {
     #[serde(rename = "type")]
     pub delta_type: String,
+    #[serde(default)]
     pub text: String,
+    #[serde(default)]
+    pub partial_json: String,
+    // thinking_delta and signature_delta events
+    #[serde(default)]
+    pub thinking: String,
+    #[serde(default)]
+    pub signature: String,
 }
 
 #[derive(Deserialize, Debug)]
@@ -114,23 +517,13 @@ pub struct MessageDeltaContent {
     pub stop_sequence: Option<String>,
 }
 
-// Update the ClaudeApiRequest to include stream option
-#[derive(Serialize)]
-pub struct ClaudeStreamApiRequest {
-    pub model: String,
-    pub max_tokens: u32,
-    pub messages: Vec<ClaudeMessage>,
-    pub stream: bool,
-}
-
 // Add thfunction to parse SSE events
 pub fn parse_sse_line(line: &str) -> Option<StreamEvent> {
-    if line.starts_with("data: ") {
-        let json = &line["data: ".len()..];
+    if let Some(json) = line.strip_prefix("data: ") {
         match serde_json::from_str(json) {
             Ok(event) => Some(event),
             Err(e) => {
-                eprintln!("Error parsing SSE data: {}", e);
+                log::warn!("Error parsing SSE data: {}", e);
                 None
             }
         }
@@ -0,0 +1,100 @@
+// `--pager`: a reply too tall for the terminal is shown through $PAGER (less by default)
+// instead of scrolling past. Replies are collected in full first, since the pager can only
+// be chosen once the length is known.
+use crate::wrap;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+/// Rows `text` takes on a terminal `columns` wide, escape codes not counted
+fn rows(text: &str, columns: usize) -> usize {
+    text.split('\n')
+        .map(|line| {
+            let mut width = 0;
+            let mut in_escape = false;
+            for c in line.chars() {
+                if in_escape || c == '\x1b' {
+                    in_escape = c == '\x1b' || !c.is_ascii_alphabetic();
+                    continue;
+                }
+                width += wrap::char_width(c);
+            }
+            width.div_ceil(columns.max(1)).max(1)
+        })
+        .sum()
+}
+
+/// Print `text` as it is if it fits on screen, otherwise hand it to the pager. Falls back to
+/// printing if the pager can't be started.
+pub fn show(text: &str) -> io::Result<()> {
+    let fits = match (wrap::terminal_height(), wrap::terminal_width()) {
+        // Leave a row for the prompt that follows
+        (Some(height), Some(width)) => rows(text, width) < height,
+        _ => true,
+    };
+    if fits || page(text).is_err() {
+        let mut stdout = io::stdout();
+        stdout.write_all(text.as_bytes())?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+fn page(text: &str) -> io::Result<()> {
+    let command = std::env::var("PAGER")
+        .ok()
+        .filter(|pager| !pager.trim().is_empty())
+        .unwrap_or_else(|| "less".to_string());
+    // PAGER may carry arguments, e.g. "less -S"
+    let mut parts = command.split_whitespace();
+    let program = parts.next().unwrap_or("less");
+    let mut pager = Command::new(program);
+    pager.args(parts).stdin(Stdio::piped());
+    // Colors have to come through as colors, not as raw escape codes
+    if std::env::var_os("LESS").is_none() {
+        pager.env("LESS", "-R");
+    }
+    let mut child = pager.spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The user may quit before reading it all, closing the pipe early
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    child.wait()?;
+    Ok(())
+}
+
+/// Where a streamed reply goes: straight to the terminal, or held back for `show`
+pub struct Output {
+    held: Option<String>,
+}
+
+impl Output {
+    pub fn new(hold: bool) -> Self {
+        Output {
+            held: hold.then(String::new),
+        }
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.held.is_some()
+    }
+
+    pub fn write(&mut self, text: &str) -> io::Result<()> {
+        match &mut self.held {
+            Some(held) => held.push_str(text),
+            None => {
+                let mut stdout = io::stdout();
+                stdout.write_all(text.as_bytes())?;
+                stdout.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Show whatever was held back
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self.held.take() {
+            Some(held) => show(&held),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+// Approximate spend from reported token usage. Prices change, so config.json can override
+// or extend this table under "pricing".
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// US dollars per million tokens
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct Price {
+    pub input: f64,
+    pub output: f64,
+}
+
+// Keyed by model name prefix, so dated snapshots like claude-3-5-haiku-20241022 match
+const PRICES: &[(&str, f64, f64)] = &[
+    ("claude-3-haiku", 0.25, 1.25),
+    ("claude-3-5-haiku", 0.80, 4.0),
+    ("claude-haiku-4-5", 1.0, 5.0),
+    ("claude-3-sonnet", 3.0, 15.0),
+    ("claude-3-5-sonnet", 3.0, 15.0),
+    ("claude-3-7-sonnet", 3.0, 15.0),
+    ("claude-sonnet-4", 3.0, 15.0),
+    ("claude-3-opus", 15.0, 75.0),
+    ("claude-opus-4", 15.0, 75.0),
+    ("claude-opus-4-5", 5.0, 25.0),
+];
+
+/// The price for `model`, preferring overrides; the longest matching prefix wins
+pub fn price_for(model: &str, overrides: &BTreeMap<String, Price>) -> Option<Price> {
+    let overridden = overrides
+        .iter()
+        .filter(|(prefix, _)| model.starts_with(prefix.as_str()))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, price)| *price);
+    overridden.or_else(|| {
+        PRICES
+            .iter()
+            .filter(|(prefix, _, _)| model.starts_with(prefix))
+            .max_by_key(|(prefix, _, _)| prefix.len())
+            .map(|&(_, input, output)| Price { input, output })
+    })
+}
+
+// Writing to the prompt cache costs a quarter more than plain input, reading a tenth
+const CACHE_WRITE_MULTIPLIER: f64 = 1.25;
+const CACHE_READ_MULTIPLIER: f64 = 0.1;
+
+pub fn cost(
+    price: Price,
+    input_tokens: u32,
+    output_tokens: u32,
+    cache_write_tokens: u32,
+    cache_read_tokens: u32,
+) -> f64 {
+    let input = input_tokens as f64
+        + cache_write_tokens as f64 * CACHE_WRITE_MULTIPLIER
+        + cache_read_tokens as f64 * CACHE_READ_MULTIPLIER;
+    (input * price.input + output_tokens as f64 * price.output) / 1_000_000.0
+}
+
+/// Small amounts keep enough digits to be meaningful
+pub fn format_cost(dollars: f64) -> String {
+    if dollars < 0.01 {
+        format!("~${:.4}", dollars)
+    } else {
+        format!("~${:.2}", dollars)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dated_snapshots_match_the_longest_prefix() {
+        let none = BTreeMap::new();
+        let price = price_for("claude-opus-4-5-20251101", &none).unwrap();
+        assert_eq!((price.input, price.output), (5.0, 25.0));
+        let price = price_for("claude-opus-4-1-20250805", &none).unwrap();
+        assert_eq!((price.input, price.output), (15.0, 75.0));
+        // Unknown models get no price rather than a guess
+        assert!(price_for("gpt-4", &none).is_none());
+    }
+
+    #[test]
+    fn config_prices_win() {
+        let overrides = BTreeMap::from([(
+            "claude-3-5-haiku".to_string(),
+            Price {
+                input: 1.0,
+                output: 2.0,
+            },
+        )]);
+        let price = price_for("claude-3-5-haiku-20241022", &overrides).unwrap();
+        assert_eq!((price.input, price.output), (1.0, 2.0));
+    }
+
+    #[test]
+    fn costs_count_cache_tokens() {
+        let price = Price {
+            input: 3.0,
+            output: 15.0,
+        };
+        assert_eq!(cost(price, 1_000_000, 1_000_000, 0, 0), 18.0);
+        assert_eq!(cost(price, 0, 0, 1_000_000, 0), 3.75);
+        assert!((cost(price, 0, 0, 0, 1_000_000) - 0.3).abs() < 1e-9);
+        assert_eq!(format_cost(0.00123), "~$0.0012");
+        assert_eq!(format_cost(1.5), "~$1.50");
+    }
+}
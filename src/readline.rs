@@ -0,0 +1,515 @@
+// A small line editor for the chat prompt: emacs-style editing keys, in-session history
+// navigated with the arrow keys, and history persisted to a file between runs.
+// When stdin is not a terminal (or on non-Unix platforms) it falls back to plain reads.
+use crate::wrap;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::path::Path;
+
+const MAX_HISTORY: usize = 1000;
+
+pub enum ReadResult {
+    Line(String),
+    /// Ctrl-C on an empty line
+    Interrupted,
+    /// Ctrl-D on an empty line, or end of input
+    Eof,
+}
+
+#[cfg(unix)]
+struct RawMode {
+    original: libc::termios,
+}
+
+#[cfg(unix)]
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        // SAFETY: tcgetattr/tcsetattr only read and write the termios struct we pass in
+        unsafe {
+            let mut original: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut raw = original;
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+            raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(RawMode { original })
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: restores the settings captured in enable()
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Tab,
+    Ctrl(u8),
+    Unknown,
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0u8; 1];
+    match input.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+    let Some(byte) = read_byte(input)? else {
+        return Ok(None);
+    };
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        127 | 8 => Key::Backspace,
+        0x1b => read_escape(input)?,
+        1..=26 => Key::Ctrl(byte + b'a' - 1),
+        0..=0x1f => Key::Unknown,
+        _ => read_utf8(input, byte)?,
+    };
+    Ok(Some(key))
+}
+
+// Arrow/home/end keys arrive as `ESC [ x`, `ESC O x` or `ESC [ n ~`
+fn read_escape(input: &mut impl Read) -> io::Result<Key> {
+    let Some(kind) = read_byte(input)? else {
+        return Ok(Key::Unknown);
+    };
+    if kind != b'[' && kind != b'O' {
+        return Ok(Key::Unknown);
+    }
+    let Some(code) = read_byte(input)? else {
+        return Ok(Key::Unknown);
+    };
+    Ok(match code {
+        b'A' => Key::Up,
+        b'B' => Key::Down,
+        b'C' => Key::Right,
+        b'D' => Key::Left,
+        b'H' => Key::Home,
+        b'F' => Key::End,
+        b'0'..=b'9' => {
+            // Consume up to the terminating '~'
+            let mut number = vec![code];
+            while let Some(next) = read_byte(input)? {
+                if next == b'~' {
+                    break;
+                }
+                number.push(next);
+            }
+            match number.as_slice() {
+                b"1" | b"7" => Key::Home,
+                b"4" | b"8" => Key::End,
+                b"3" => Key::Delete,
+                _ => Key::Unknown,
+            }
+        }
+        _ => Key::Unknown,
+    })
+}
+
+fn read_utf8(input: &mut impl Read, first: u8) -> io::Result<Key> {
+    let length = match first {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1,
+    };
+    let mut bytes = vec![first];
+    for _ in 1..length {
+        match read_byte(input)? {
+            Some(byte) => bytes.push(byte),
+            None => break,
+        }
+    }
+    Ok(
+        match std::str::from_utf8(&bytes)
+            .ok()
+            .and_then(|s| s.chars().next())
+        {
+            Some(c) => Key::Char(c),
+            None => Key::Unknown,
+        },
+    )
+}
+
+/// Supplies tab-completion candidates for the text before the cursor
+pub trait Completer {
+    /// Returns the byte offset where the completed token starts and the candidates for it
+    fn complete(&self, line: &str) -> Option<(usize, Vec<String>)>;
+}
+
+pub struct Editor {
+    history: Vec<String>,
+    completer: Option<Box<dyn Completer>>,
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Editor {
+            history: Vec::new(),
+            completer: None,
+        }
+    }
+
+    pub fn set_completer(&mut self, completer: Box<dyn Completer>) {
+        self.completer = Some(completer);
+    }
+
+    pub fn load_history(&mut self, path: &Path) -> io::Result<()> {
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                self.history = contents.lines().map(str::to_string).collect();
+                Ok(())
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save_history(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut contents = self.history.join("\n");
+        contents.push('\n');
+        fs::write(path, contents)
+    }
+
+    pub fn add_history_entry(&mut self, line: &str) {
+        // Multi-line entries can't be stored one per line, and repeats aren't useful
+        if line.is_empty() || line.contains('\n') || self.history.last().is_some_and(|l| l == line)
+        {
+            return;
+        }
+        self.history.push(line.to_string());
+        if self.history.len() > MAX_HISTORY {
+            self.history.remove(0);
+        }
+    }
+
+    pub fn readline(&mut self, prompt: &str) -> io::Result<ReadResult> {
+        self.readline_with_initial(prompt, "")
+    }
+
+    /// Like `readline`, with `initial` already typed in and the cursor after it. Without a
+    /// terminal to edit in, the initial text is dropped.
+    pub fn readline_with_initial(&mut self, prompt: &str, initial: &str) -> io::Result<ReadResult> {
+        #[cfg(unix)]
+        if io::stdin().is_terminal() {
+            if let Ok(raw_mode) = RawMode::enable() {
+                let result = self.edit_line(prompt, initial);
+                drop(raw_mode);
+                return result;
+            }
+        }
+        read_plain_line(prompt)
+    }
+
+    fn edit_line(&mut self, prompt: &str, initial: &str) -> io::Result<ReadResult> {
+        let mut stdin = io::stdin().lock();
+        let mut stdout = io::stdout();
+        let mut line: Vec<char> = initial.chars().collect();
+        let mut cursor = line.len();
+        // Index into history while browsing with up/down; the edited line is kept aside
+        let mut history_index = self.history.len();
+        let mut saved_line: Vec<char> = Vec::new();
+        let mut screen = Screen::default();
+
+        screen.refresh(&mut stdout, prompt, &line, cursor)?;
+        loop {
+            let Some(key) = read_key(&mut stdin)? else {
+                return Ok(ReadResult::Eof);
+            };
+            match key {
+                Key::Enter => {
+                    screen.refresh(&mut stdout, prompt, &line, line.len())?;
+                    write!(stdout, "\r\n")?;
+                    stdout.flush()?;
+                    return Ok(ReadResult::Line(line.into_iter().collect()));
+                }
+                Key::Ctrl(b'c') => {
+                    screen.refresh(&mut stdout, prompt, &line, line.len())?;
+                    write!(stdout, "^C\r\n")?;
+                    stdout.flush()?;
+                    screen = Screen::default();
+                    if line.is_empty() {
+                        return Ok(ReadResult::Interrupted);
+                    }
+                    // With text typed, Ctrl-C just discards the line
+                    line.clear();
+                    cursor = 0;
+                    history_index = self.history.len();
+                }
+                Key::Ctrl(b'd') if line.is_empty() => {
+                    write!(stdout, "\r\n")?;
+                    stdout.flush()?;
+                    return Ok(ReadResult::Eof);
+                }
+                Key::Ctrl(b'd') | Key::Delete => {
+                    if cursor < line.len() {
+                        line.remove(cursor);
+                    }
+                }
+                Key::Char(c) => {
+                    line.insert(cursor, c);
+                    cursor += 1;
+                }
+                Key::Backspace => {
+                    if cursor > 0 {
+                        cursor -= 1;
+                        line.remove(cursor);
+                    }
+                }
+                Key::Left | Key::Ctrl(b'b') => cursor = cursor.saturating_sub(1),
+                Key::Right | Key::Ctrl(b'f') => cursor = (cursor + 1).min(line.len()),
+                Key::Home | Key::Ctrl(b'a') => cursor = 0,
+                Key::End | Key::Ctrl(b'e') => cursor = line.len(),
+                Key::Ctrl(b'k') => line.truncate(cursor),
+                Key::Ctrl(b'u') => {
+                    line.drain(..cursor);
+                    cursor = 0;
+                }
+                Key::Ctrl(b'w') => {
+                    let mut start = cursor;
+                    while start > 0 && line[start - 1].is_whitespace() {
+                        start -= 1;
+                    }
+                    while start > 0 && !line[start - 1].is_whitespace() {
+                        start -= 1;
+                    }
+                    line.drain(start..cursor);
+                    cursor = start;
+                }
+                Key::Ctrl(b'l') => {
+                    write!(stdout, "\x1b[2J\x1b[H")?;
+                    screen = Screen::default();
+                }
+                Key::Up | Key::Ctrl(b'p') => {
+                    if history_index > 0 {
+                        if history_index == self.history.len() {
+                            saved_line = line.clone();
+                        }
+                        history_index -= 1;
+                        line = self.history[history_index].chars().collect();
+                        cursor = line.len();
+                    }
+                }
+                Key::Down | Key::Ctrl(b'n') => {
+                    if history_index < self.history.len() {
+                        history_index += 1;
+                        line = if history_index == self.history.len() {
+                            saved_line.clone()
+                        } else {
+                            self.history[history_index].chars().collect()
+                        };
+                        cursor = line.len();
+                    }
+                }
+                Key::Tab => {
+                    self.complete(&mut stdout, &mut screen, prompt, &mut line, &mut cursor)?
+                }
+                Key::Ctrl(_) | Key::Unknown => {}
+            }
+            screen.refresh(&mut stdout, prompt, &line, cursor)?;
+        }
+    }
+}
+
+impl Editor {
+    // Single match: fill it in. Several: extend to their common prefix, or list them
+    fn complete(
+        &self,
+        stdout: &mut io::Stdout,
+        screen: &mut Screen,
+        prompt: &str,
+        line: &mut Vec<char>,
+        cursor: &mut usize,
+    ) -> io::Result<()> {
+        let Some(completer) = &self.completer else {
+            return Ok(());
+        };
+        let before: String = line[..*cursor].iter().collect();
+        let Some((start, candidates)) = completer.complete(&before) else {
+            return Ok(());
+        };
+        let token = &before[start..];
+        let replacement = match candidates.as_slice() {
+            [] => return Ok(()),
+            [only] => format!("{} ", only),
+            _ => {
+                let common = common_prefix(&candidates);
+                if common.len() > token.len() {
+                    common
+                } else {
+                    // Listed below the whole line, which is then drawn afresh under them
+                    screen.refresh(stdout, prompt, line, line.len())?;
+                    write!(stdout, "\r\n{}\r\n", candidates.join("  "))?;
+                    *screen = Screen::default();
+                    return Ok(());
+                }
+            }
+        };
+        let start_char = before[..start].chars().count();
+        line.splice(start_char..*cursor, replacement.chars());
+        *cursor = start_char + replacement.chars().count();
+        screen.refresh(stdout, prompt, line, *cursor)
+    }
+}
+
+fn common_prefix(candidates: &[String]) -> String {
+    let mut prefix = candidates[0].clone();
+    for candidate in &candidates[1..] {
+        while !candidate.starts_with(&prefix) {
+            prefix.pop();
+        }
+    }
+    prefix
+}
+
+// A line longer than the terminal is wide wraps onto the rows below, so a redraw has to go
+// back up to the row the prompt is on first. This keeps track of which row that was.
+#[derive(Default)]
+struct Screen {
+    // Rows between the prompt's row and the one the cursor was left on
+    cursor_row: usize,
+}
+
+impl Screen {
+    // Redraw the prompt and line, then move the terminal cursor back to `cursor`
+    fn refresh(
+        &mut self,
+        stdout: &mut io::Stdout,
+        prompt: &str,
+        line: &[char],
+        cursor: usize,
+    ) -> io::Result<()> {
+        let columns = wrap::terminal_width().unwrap_or(80);
+        if self.cursor_row > 0 {
+            write!(stdout, "\x1b[{}A", self.cursor_row)?;
+        }
+        let text: String = line.iter().collect();
+        write!(stdout, "\r{}{}\x1b[J", prompt, text)?;
+
+        let (end_row, end_column) = position(visible(prompt).chain(line.iter().copied()), columns);
+        // Text that exactly fills its last row leaves the terminal cursor waiting at the
+        // edge rather than on the next row, where the arithmetic below has it
+        if end_column == 0 && end_row > 0 {
+            write!(stdout, "\r\n")?;
+        }
+        let (mut row, mut column) = position(
+            visible(prompt).chain(line[..cursor].iter().copied()),
+            columns,
+        );
+        // A wide character that doesn't fit at the end of a row is drawn on the next one
+        if line
+            .get(cursor)
+            .is_some_and(|&c| column + wrap::char_width(c) > columns)
+        {
+            (row, column) = (row + 1, 0);
+        }
+        if end_row > row {
+            write!(stdout, "\x1b[{}A", end_row - row)?;
+        }
+        write!(stdout, "\r")?;
+        if column > 0 {
+            write!(stdout, "\x1b[{}C", column)?;
+        }
+        self.cursor_row = row;
+        stdout.flush()
+    }
+}
+
+// The prompt's characters without the escape codes that color it
+fn visible(prompt: &str) -> impl Iterator<Item = char> + '_ {
+    let mut in_escape = false;
+    prompt.chars().filter(move |&c| {
+        if in_escape || c == '\x1b' {
+            in_escape = c == '\x1b' || !c.is_ascii_alphabetic();
+            return false;
+        }
+        true
+    })
+}
+
+// Row and column reached by printing `chars` from the start of a row `columns` wide
+fn position(chars: impl Iterator<Item = char>, columns: usize) -> (usize, usize) {
+    let (mut row, mut column) = (0, 0);
+    for c in chars {
+        let width = wrap::char_width(c);
+        if column + width > columns {
+            row += 1;
+            column = 0;
+        }
+        column += width;
+    }
+    if column >= columns {
+        (row + 1, 0)
+    } else {
+        (row, column)
+    }
+}
+
+fn read_plain_line(prompt: &str) -> io::Result<ReadResult> {
+    print!("{}", prompt);
+    io::stdout().flush()?;
+    let mut input = String::new();
+    if io::stdin().lock().read_line(&mut input)? == 0 {
+        return Ok(ReadResult::Eof);
+    }
+    Ok(ReadResult::Line(input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_count_columns_and_wrapped_rows() {
+        let cases = [
+            ("", (0, 0)),
+            ("abc", (0, 3)),
+            ("abcd", (0, 4)),
+            ("abcde", (1, 0)),
+            ("abcdef", (1, 1)),
+            ("日本", (0, 4)),
+            ("日本語", (1, 2)),
+            ("日本語x", (1, 3)),
+            ("abc日", (1, 0)),
+            ("abcd日", (1, 2)),
+            ("🎉🎉🎉", (1, 2)),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(position(text.chars(), 5), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn prompt_escapes_take_no_columns() {
+        let prompt = "\x1b[1;32mYou:\x1b[0m ";
+        assert_eq!(visible(prompt).collect::<String>(), "You: ");
+    }
+}
@@ -0,0 +1,746 @@
+// Scrubbing sensitive text out of prompts before they leave the machine. Patterns come
+// from "redact_patterns" in config.json and use a common regex subset: literals, `.`,
+// classes like `[a-z0-9._%+-]` and `[^ ]`, the escapes `\d \w \s \b` (and their negations),
+// groups with `|`, the quantifiers `* + ? {n} {n,} {n,m}` (lazy with a trailing `?`), the
+// anchors `^ $` and a leading `(?i)` for case-insensitive matching.
+//
+// A pattern is compiled to a small program and run as a Pike VM: every way the pattern could
+// go is followed at once, one input character at a time, so matching takes time linear in
+// the input and never recurses, however long the text or however nested the quantifiers.
+use crate::modules::{InputContentBlock, MessageContent};
+
+const REPLACEMENT: &str = "[REDACTED]";
+// Bounded quantifiers are compiled by copying what they repeat, so `(x{100}){100}` and the
+// like are refused instead of growing without limit
+const MAX_PROGRAM: usize = 10_000;
+
+#[derive(Clone, Copy, Debug)]
+enum Perl {
+    Digit,
+    Word,
+    Space,
+}
+
+impl Perl {
+    fn matches(self, c: char) -> bool {
+        match self {
+            Perl::Digit => c.is_ascii_digit(),
+            Perl::Word => c.is_alphanumeric() || c == '_',
+            Perl::Space => c.is_whitespace(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum ClassItem {
+    Range(char, char),
+    Perl(Perl, bool),
+}
+
+// What a single input character is checked against
+#[derive(Clone, Debug)]
+enum Test {
+    Char(char),
+    Any,
+    Perl(Perl, bool),
+    Class {
+        items: Vec<ClassItem>,
+        negated: bool,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Assertion {
+    Start,
+    End,
+    WordBoundary(bool),
+}
+
+enum Node {
+    Test(Test),
+    Assert(Assertion),
+    Group(Vec<Vec<Node>>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+        greedy: bool,
+    },
+}
+
+// One step of a compiled pattern
+#[derive(Debug)]
+enum Inst {
+    // Consume a character that passes the test
+    Test(Test),
+    Assert(Assertion),
+    // Go both ways, preferring the first
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+/// A compiled redaction pattern
+pub struct Pattern {
+    program: Vec<Inst>,
+    case_insensitive: bool,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Result<Pattern, String> {
+        let (case_insensitive, body) = match pattern.strip_prefix("(?i)") {
+            Some(body) => (true, body),
+            None => (false, pattern),
+        };
+        let mut parser = Parser {
+            chars: body.chars().collect(),
+            pos: 0,
+        };
+        let alternatives = parser.alternation()?;
+        if parser.pos < parser.chars.len() {
+            return Err(format!("unmatched ')' at position {}", parser.pos + 1));
+        }
+        let mut compiler = Compiler {
+            program: Vec::new(),
+        };
+        compiler.alternation(&alternatives)?;
+        compiler.push(Inst::Match);
+        Ok(Pattern {
+            program: compiler.program,
+            case_insensitive,
+        })
+    }
+
+    /// Non-overlapping (start, end) character ranges of every non-empty match
+    fn find_all(&self, input: &[char]) -> Vec<(usize, usize)> {
+        let matcher = Matcher {
+            program: &self.program,
+            input,
+            case_insensitive: self.case_insensitive,
+        };
+        let mut matches = Vec::new();
+        let mut start = 0;
+        while let Some((found, end)) = matcher.find_at(start) {
+            matches.push((found, end));
+            start = end;
+        }
+        matches
+    }
+}
+
+// \d, \w, \s and their negations
+fn perl_class(c: char) -> Option<(Perl, bool)> {
+    match c {
+        'd' => Some((Perl::Digit, false)),
+        'D' => Some((Perl::Digit, true)),
+        'w' => Some((Perl::Word, false)),
+        'W' => Some((Perl::Word, true)),
+        's' => Some((Perl::Space, false)),
+        'S' => Some((Perl::Space, true)),
+        _ => None,
+    }
+}
+
+// The character an escape like \n or \. stands for
+fn literal_escape(c: char) -> char {
+    match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        c => c,
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn alternation(&mut self) -> Result<Vec<Vec<Node>>, String> {
+        let mut alternatives = vec![self.sequence()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            alternatives.push(self.sequence()?);
+        }
+        Ok(alternatives)
+    }
+
+    fn sequence(&mut self) -> Result<Vec<Node>, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.quantifier(atom)?);
+        }
+        Ok(nodes)
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let position = self.pos + 1;
+        match self.next() {
+            Some('(') => {
+                if self.chars[self.pos..].starts_with(&['?', ':']) {
+                    self.pos += 2;
+                }
+                let alternatives = self.alternation()?;
+                if self.next() != Some(')') {
+                    return Err(format!("unclosed '(' at position {}", position));
+                }
+                Ok(Node::Group(alternatives))
+            }
+            Some('[') => self.class(position),
+            Some('.') => Ok(Node::Test(Test::Any)),
+            Some('^') => Ok(Node::Assert(Assertion::Start)),
+            Some('$') => Ok(Node::Assert(Assertion::End)),
+            Some('\\') => self.escape(),
+            Some(c @ ('*' | '+' | '?')) => Err(format!(
+                "nothing to repeat before '{}' at position {}",
+                c, position
+            )),
+            Some(c) => Ok(Node::Test(Test::Char(c))),
+            None => Err("unexpected end of pattern".to_string()),
+        }
+    }
+
+    fn escape(&mut self) -> Result<Node, String> {
+        let c = self
+            .next()
+            .ok_or_else(|| "trailing '\\' at end of pattern".to_string())?;
+        if let Some((class, negated)) = perl_class(c) {
+            return Ok(Node::Test(Test::Perl(class, negated)));
+        }
+        Ok(match c {
+            'b' => Node::Assert(Assertion::WordBoundary(true)),
+            'B' => Node::Assert(Assertion::WordBoundary(false)),
+            c => Node::Test(Test::Char(literal_escape(c))),
+        })
+    }
+
+    fn class(&mut self, position: usize) -> Result<Node, String> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut items = Vec::new();
+        let mut first = true;
+        loop {
+            let c = self
+                .next()
+                .ok_or_else(|| format!("unclosed '[' at position {}", position))?;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+            let low = if c == '\\' {
+                let escaped = self
+                    .next()
+                    .ok_or_else(|| format!("unclosed '[' at position {}", position))?;
+                if let Some((class, negated)) = perl_class(escaped) {
+                    items.push(ClassItem::Perl(class, negated));
+                    continue;
+                }
+                literal_escape(escaped)
+            } else {
+                c
+            };
+            // A '-' makes a range unless it comes last, as in [a-z-]
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&c| c != ']') {
+                self.pos += 1;
+                let high = match self.next() {
+                    Some('\\') => self
+                        .next()
+                        .ok_or_else(|| format!("unclosed '[' at position {}", position))?,
+                    Some(high) => high,
+                    None => return Err(format!("unclosed '[' at position {}", position)),
+                };
+                if high < low {
+                    return Err(format!("invalid range {}-{} in class", low, high));
+                }
+                items.push(ClassItem::Range(low, high));
+            } else {
+                items.push(ClassItem::Range(low, low));
+            }
+        }
+        Ok(Node::Test(Test::Class { items, negated }))
+    }
+
+    fn quantifier(&mut self, atom: Node) -> Result<Node, String> {
+        let (min, max) = match self.peek() {
+            Some('*') => {
+                self.pos += 1;
+                (0, None)
+            }
+            Some('+') => {
+                self.pos += 1;
+                (1, None)
+            }
+            Some('?') => {
+                self.pos += 1;
+                (0, Some(1))
+            }
+            Some('{') => match self.counted() {
+                Some(bounds) => bounds,
+                // Not a valid {n,m}: the brace is a literal and gets parsed as the next atom
+                None => return Ok(atom),
+            },
+            _ => return Ok(atom),
+        };
+        if max.is_some_and(|max| max < min) {
+            return Err(format!("invalid repeat {{{},{}}}", min, max.unwrap_or(0)));
+        }
+        let greedy = self.peek() != Some('?');
+        if !greedy {
+            self.pos += 1;
+        }
+        Ok(Node::Repeat {
+            node: Box::new(atom),
+            min,
+            max,
+            greedy,
+        })
+    }
+
+    // {n}, {n,} or {n,m}, consumed only when well formed
+    fn counted(&mut self) -> Option<(usize, Option<usize>)> {
+        let rest: String = self.chars[self.pos + 1..].iter().collect();
+        let close = rest.find('}')?;
+        let inner = &rest[..close];
+        let bounds = match inner.split_once(',') {
+            None => {
+                let n = inner.parse().ok()?;
+                (n, Some(n))
+            }
+            Some((min, "")) => (min.parse().ok()?, None),
+            Some((min, max)) => (min.parse().ok()?, Some(max.parse().ok()?)),
+        };
+        self.pos += inner.chars().count() + 2;
+        Some(bounds)
+    }
+}
+
+struct Compiler {
+    program: Vec<Inst>,
+}
+
+impl Compiler {
+    fn push(&mut self, inst: Inst) -> usize {
+        self.program.push(inst);
+        self.program.len() - 1
+    }
+
+    fn split(greedy: bool, body: usize, out: usize) -> Inst {
+        if greedy {
+            Inst::Split(body, out)
+        } else {
+            Inst::Split(out, body)
+        }
+    }
+
+    fn alternation(&mut self, alternatives: &[Vec<Node>]) -> Result<(), String> {
+        let mut jumps = Vec::new();
+        for (index, alternative) in alternatives.iter().enumerate() {
+            if index + 1 == alternatives.len() {
+                self.sequence(alternative)?;
+                break;
+            }
+            let split = self.push(Inst::Split(0, 0));
+            self.sequence(alternative)?;
+            jumps.push(self.push(Inst::Jump(0)));
+            self.program[split] = Inst::Split(split + 1, self.program.len());
+        }
+        let end = self.program.len();
+        for jump in jumps {
+            self.program[jump] = Inst::Jump(end);
+        }
+        Ok(())
+    }
+
+    fn sequence(&mut self, nodes: &[Node]) -> Result<(), String> {
+        nodes.iter().try_for_each(|node| self.node(node))
+    }
+
+    fn node(&mut self, node: &Node) -> Result<(), String> {
+        if self.program.len() > MAX_PROGRAM {
+            return Err("pattern is too large".to_string());
+        }
+        match node {
+            Node::Test(test) => {
+                self.push(Inst::Test(test.clone()));
+            }
+            Node::Assert(assertion) => {
+                self.push(Inst::Assert(*assertion));
+            }
+            Node::Group(alternatives) => self.alternation(alternatives)?,
+            Node::Repeat {
+                node,
+                min,
+                max,
+                greedy,
+            } => {
+                for _ in 0..*min {
+                    self.node(node)?;
+                }
+                match max {
+                    None => {
+                        let split = self.push(Inst::Split(0, 0));
+                        self.node(node)?;
+                        self.push(Inst::Jump(split));
+                        self.program[split] = Self::split(*greedy, split + 1, self.program.len());
+                    }
+                    Some(max) => {
+                        let mut splits = Vec::new();
+                        for _ in *min..*max {
+                            splits.push(self.push(Inst::Split(0, 0)));
+                            self.node(node)?;
+                        }
+                        let out = self.program.len();
+                        for split in splits {
+                            self.program[split] = Self::split(*greedy, split + 1, out);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// The threads alive at one input position, in priority order, each as the instruction it
+// is waiting on and where its match started
+struct Threads {
+    list: Vec<(usize, usize)>,
+    seen: Vec<bool>,
+    visited: Vec<usize>,
+}
+
+impl Threads {
+    fn new(size: usize) -> Threads {
+        Threads {
+            list: Vec::new(),
+            seen: vec![false; size],
+            visited: Vec::new(),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.list.clear();
+        for pc in self.visited.drain(..) {
+            self.seen[pc] = false;
+        }
+    }
+
+    // Whether `pc` is new at this position; a thread that gets somewhere first wins it
+    fn visit(&mut self, pc: usize) -> bool {
+        if self.seen[pc] {
+            return false;
+        }
+        self.seen[pc] = true;
+        self.visited.push(pc);
+        true
+    }
+}
+
+struct Matcher<'a> {
+    program: &'a [Inst],
+    input: &'a [char],
+    case_insensitive: bool,
+}
+
+impl Matcher<'_> {
+    fn same(&self, a: char, b: char) -> bool {
+        a == b || (self.case_insensitive && a.to_lowercase().eq(b.to_lowercase()))
+    }
+
+    fn in_range(&self, c: char, low: char, high: char) -> bool {
+        if (low..=high).contains(&c) {
+            return true;
+        }
+        self.case_insensitive
+            && c.to_lowercase()
+                .chain(c.to_uppercase())
+                .any(|variant| (low..=high).contains(&variant))
+    }
+
+    fn is_word(&self, pos: usize) -> bool {
+        self.input.get(pos).is_some_and(|&c| Perl::Word.matches(c))
+    }
+
+    fn accepts(&self, test: &Test, c: char) -> bool {
+        match test {
+            Test::Char(expected) => self.same(c, *expected),
+            Test::Any => c != '\n',
+            Test::Perl(class, negated) => class.matches(c) != *negated,
+            Test::Class { items, negated } => {
+                let found = items.iter().any(|item| match item {
+                    ClassItem::Range(low, high) => self.in_range(c, *low, *high),
+                    ClassItem::Perl(class, negated) => class.matches(c) != *negated,
+                });
+                found != *negated
+            }
+        }
+    }
+
+    fn holds(&self, assertion: Assertion, pos: usize) -> bool {
+        match assertion {
+            Assertion::Start => pos == 0,
+            Assertion::End => pos == self.input.len(),
+            Assertion::WordBoundary(expected) => {
+                let before = pos > 0 && self.is_word(pos - 1);
+                (before != self.is_word(pos)) == expected
+            }
+        }
+    }
+
+    // Follow jumps, splits and assertions from `pc` at `pos`, adding a thread for each
+    // instruction reached that waits on input. Higher-priority branches are added first.
+    fn add(&self, threads: &mut Threads, pc: usize, start: usize, pos: usize) {
+        let mut stack = vec![pc];
+        while let Some(pc) = stack.pop() {
+            if !threads.visit(pc) {
+                continue;
+            }
+            match &self.program[pc] {
+                Inst::Jump(to) => stack.push(*to),
+                Inst::Split(first, second) => {
+                    stack.push(*second);
+                    stack.push(*first);
+                }
+                Inst::Assert(assertion) => {
+                    if self.holds(*assertion, pos) {
+                        stack.push(pc + 1);
+                    }
+                }
+                Inst::Test(_) | Inst::Match => threads.list.push((pc, start)),
+            }
+        }
+    }
+
+    /// The leftmost non-empty match starting at or after `from`. Where several could end
+    /// there, the one the pattern prefers wins, as with a backtracking engine: the first
+    /// alternative, and as much (or with a lazy quantifier, as little) as possible.
+    fn find_at(&self, from: usize) -> Option<(usize, usize)> {
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+        let mut found = None;
+        for pos in from..=self.input.len() {
+            // A match starting here ranks below every one that started earlier
+            if found.is_none() {
+                self.add(&mut current, 0, pos, pos);
+            } else if current.list.is_empty() {
+                break;
+            }
+            next.clear();
+            for index in 0..current.list.len() {
+                let (pc, start) = current.list[index];
+                match &self.program[pc] {
+                    Inst::Match if start < pos => {
+                        found = Some((start, pos));
+                        // Everything after this thread is less preferred
+                        break;
+                    }
+                    Inst::Test(test)
+                        if self.input.get(pos).is_some_and(|&c| self.accepts(test, c)) =>
+                    {
+                        self.add(&mut next, pc + 1, start, pos + 1);
+                    }
+                    _ => {}
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+        }
+        found
+    }
+}
+
+/// The configured patterns, applied to everything the user sends
+pub struct Redactor {
+    patterns: Vec<Pattern>,
+}
+
+impl Redactor {
+    /// Compile the patterns, reporting (and skipping) any that are invalid
+    pub fn new(patterns: &[String]) -> Redactor {
+        let patterns = patterns
+            .iter()
+            .filter_map(|pattern| match Pattern::new(pattern) {
+                Ok(compiled) => Some(compiled),
+                Err(e) => {
+                    eprintln!("Ignoring redaction pattern {:?}: {}", pattern, e);
+                    None
+                }
+            })
+            .collect();
+        Redactor { patterns }
+    }
+
+    pub fn disabled() -> Redactor {
+        Redactor {
+            patterns: Vec::new(),
+        }
+    }
+
+    /// Replace every match with a placeholder; returns the new text and how many fired
+    pub fn redact(&self, text: &str) -> (String, usize) {
+        let mut text = text.to_string();
+        let mut count = 0;
+        for pattern in &self.patterns {
+            let chars: Vec<char> = text.chars().collect();
+            let matches = pattern.find_all(&chars);
+            if matches.is_empty() {
+                continue;
+            }
+            count += matches.len();
+            let mut redacted = String::new();
+            let mut last = 0;
+            for (start, end) in matches {
+                redacted.extend(&chars[last..start]);
+                redacted.push_str(REPLACEMENT);
+                last = end;
+            }
+            redacted.extend(&chars[last..]);
+            text = redacted;
+        }
+        (text, count)
+    }
+
+    /// Redact the text of a message, leaving images alone
+    pub fn redact_content(&self, content: MessageContent) -> (MessageContent, usize) {
+        if self.patterns.is_empty() {
+            return (content, 0);
+        }
+        match content {
+            MessageContent::Text(text) => {
+                let (text, count) = self.redact(&text);
+                (MessageContent::Text(text), count)
+            }
+            MessageContent::Blocks(blocks) => {
+                let mut total = 0;
+                let blocks = blocks
+                    .into_iter()
+                    .map(|block| match block {
+                        InputContentBlock::Text {
+                            text,
+                            cache_control,
+                        } => {
+                            let (text, count) = self.redact(&text);
+                            total += count;
+                            InputContentBlock::Text {
+                                text,
+                                cache_control,
+                            }
+                        }
+                        image => image,
+                    })
+                    .collect();
+                (MessageContent::Blocks(blocks), total)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The text of every match
+    fn matches(pattern: &str, text: &str) -> Vec<String> {
+        let chars: Vec<char> = text.chars().collect();
+        Pattern::new(pattern)
+            .unwrap()
+            .find_all(&chars)
+            .into_iter()
+            .map(|(start, end)| chars[start..end].iter().collect())
+            .collect()
+    }
+
+    #[test]
+    fn patterns_match_like_a_regex() {
+        let cases: &[(&str, &str, &[&str])] = &[
+            ("cat", "a cat and a cat", &["cat", "cat"]),
+            ("c.t", "cut cot c\nt", &["cut", "cot"]),
+            (r"\d+", "call 555 1234", &["555", "1234"]),
+            (
+                r"\w+@\w+\.com",
+                "mail bob@example.com now",
+                &["bob@example.com"],
+            ),
+            ("[a-c]+", "abcdcba", &["abc", "cba"]),
+            ("[^ ]+", "two words", &["two", "words"]),
+            ("[a-z-]+", "well-known", &["well-known"]),
+            ("cat|category", "category", &["cat"]),
+            ("(cat|dog)s?", "cats dog", &["cats", "dog"]),
+            ("(?:ab)+", "ababa", &["abab"]),
+            ("a{2}", "aaaaa", &["aa", "aa"]),
+            ("a{2,}", "a aa aaaa", &["aa", "aaaa"]),
+            ("a{1,2}", "aaa", &["aa", "a"]),
+            ("x{y", "x{y", &["x{y"]),
+            ("<.+>", "<a><b>", &["<a><b>"]),
+            ("<.+?>", "<a><b>", &["<a>", "<b>"]),
+            ("^ab", "abab", &["ab"]),
+            ("ab$", "abab", &["ab"]),
+            (r"\bkey\b", "key keys monkey key", &["key", "key"]),
+            (r"\Bkey", "key monkey", &["key"]),
+            ("(?i)secret", "Secret SECRET", &["Secret", "SECRET"]),
+            ("(?i)[a-c]+", "ABC", &["ABC"]),
+            ("a*", "baaa", &["aaa"]),
+            ("(a*)*b", "aab", &["aab"]),
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(
+                &matches(pattern, text),
+                expected,
+                "{} on {:?}",
+                pattern,
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_patterns_are_refused() {
+        for pattern in [
+            "(ab",
+            "ab)",
+            "[ab",
+            "*a",
+            "a{3,1}",
+            "[z-a]",
+            "a\\",
+            "(a{100}){200}",
+        ] {
+            assert!(Pattern::new(pattern).is_err(), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn long_input_does_not_overflow_or_blow_up() {
+        let word = "a".repeat(100_000);
+        assert_eq!(matches(r"\S+", &word).len(), 1);
+        assert_eq!(matches("[A-Za-z0-9+/]{20,}", &word).len(), 1);
+        assert!(matches(r"[a-z0-9._%+-]+@[a-z0-9.-]+\.[a-z]{2,}", &word).is_empty());
+        let started = std::time::Instant::now();
+        assert!(matches("(a+)+b", &"a".repeat(1_000)).is_empty());
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn redact_replaces_each_match() {
+        let redactor = Redactor::new(&[r"sk-\w+".to_string(), r"\d{3}-\d{4}".to_string()]);
+        let (text, count) = redactor.redact("key sk-abc123, call 555-1234");
+        assert_eq!(text, "key [REDACTED], call [REDACTED]");
+        assert_eq!(count, 2);
+    }
+}
@@ -0,0 +1,30 @@
+// The `--log-file` audit trail: one JSON line per API exchange with the request, the reply
+// or error, token usage and latency. The API key travels in a header and is never written.
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+pub struct RequestLog {
+    // Batch mode sends requests concurrently, so lines are written one at a time
+    file: Mutex<File>,
+}
+
+impl RequestLog {
+    /// Open for appending, creating the file if needed
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(RequestLog {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Append one entry. Each line goes out in a single write, which O_APPEND keeps whole
+    /// even with other processes logging to the same file.
+    pub fn record(&self, entry: &serde_json::Value) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(&line)
+    }
+}
@@ -0,0 +1,303 @@
+// --json-schema: ask for a reply in JSON and check it against a schema. The check covers the
+// keywords extraction schemas tend to use: type, enum, const, properties, required,
+// additionalProperties, items, the length, size and range limits, and allOf, anyOf and
+// oneOf. Anything else, such as $ref or pattern, is accepted as it is.
+use serde_json::{Map, Value};
+use std::io;
+use std::path::Path;
+
+pub fn load(path: &Path) -> io::Result<Value> {
+    let text = std::fs::read_to_string(path)?;
+    serde_json::from_str(&text).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("not a JSON schema: {}", e),
+        )
+    })
+}
+
+/// The system prompt with the schema added to it
+pub fn instructions(system: Option<&str>, schema: &Value) -> String {
+    let schema = serde_json::to_string_pretty(schema).unwrap_or_default();
+    let guidance = format!(
+        "Reply with only a JSON value that matches this JSON Schema, with nothing before or \
+         after it:\n{}",
+        schema
+    );
+    match system {
+        Some(system) => format!("{}\n\n{}", system, guidance),
+        None => guidance,
+    }
+}
+
+/// How the reply is made to begin, so it starts straight in on the JSON
+pub fn prefill(schema: &Value) -> Option<&'static str> {
+    match schema.get("type").and_then(Value::as_str) {
+        Some("object") => Some("{"),
+        Some("array") => Some("["),
+        None if schema.get("properties").is_some() => Some("{"),
+        _ => None,
+    }
+}
+
+/// The JSON value at the start of a reply; anything said after it is ignored
+pub fn parse(reply: &str) -> Result<Value, String> {
+    let text = reply.trim();
+    let text = text
+        .strip_prefix("```json")
+        .or_else(|| text.strip_prefix("```"))
+        .unwrap_or(text);
+    match serde_json::Deserializer::from_str(text)
+        .into_iter::<Value>()
+        .next()
+    {
+        Some(Ok(value)) => Ok(value),
+        Some(Err(e)) => Err(format!("the reply is not valid JSON: {}", e)),
+        None => Err("the reply is empty".to_string()),
+    }
+}
+
+/// What to tell Claude when its reply didn't match
+pub fn correction(problems: &[String]) -> String {
+    let list: Vec<String> = problems
+        .iter()
+        .map(|problem| format!("- {}", problem))
+        .collect();
+    format!(
+        "That doesn't match the schema:\n{}\nReply again with only the corrected JSON.",
+        list.join("\n")
+    )
+}
+
+/// Everywhere `value` doesn't match `schema`, each starting with the path to it
+pub fn validate(schema: &Value, value: &Value) -> Vec<String> {
+    let mut problems = Vec::new();
+    check(schema, value, "$", &mut problems);
+    problems
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn has_type(name: &str, value: &Value) -> bool {
+    match name {
+        "integer" => value.as_f64().is_some_and(|number| number.fract() == 0.0),
+        "number" => value.is_number(),
+        // Types this doesn't know can't be checked
+        "null" | "boolean" | "string" | "array" | "object" => type_name(value) == name,
+        _ => true,
+    }
+}
+
+// The number a size or range keyword such as minItems is set to
+fn limit(schema: &Map<String, Value>, keyword: &str) -> Option<f64> {
+    schema.get(keyword).and_then(Value::as_f64)
+}
+
+// Whether a number is outside a bound, as a range keyword sees it
+type Outside = fn(f64, f64) -> bool;
+
+fn check(schema: &Value, value: &Value, path: &str, problems: &mut Vec<String>) {
+    // `true` and `{}` allow anything, `false` nothing
+    let schema = match schema {
+        Value::Object(schema) => schema,
+        Value::Bool(false) => {
+            problems.push(format!("{}: no value is allowed here", path));
+            return;
+        }
+        _ => return,
+    };
+
+    let types: Vec<&str> = match schema.get("type") {
+        Some(Value::String(name)) => vec![name.as_str()],
+        Some(Value::Array(names)) => names.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    if !types.is_empty() && !types.iter().any(|name| has_type(name, value)) {
+        problems.push(format!(
+            "{}: expected {}, got {}",
+            path,
+            types.join(" or "),
+            type_name(value)
+        ));
+        // Nothing below makes sense for a value of the wrong type
+        return;
+    }
+    if let Some(Value::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(value) {
+            let allowed: Vec<String> = allowed.iter().map(Value::to_string).collect();
+            problems.push(format!(
+                "{}: {} is not one of {}",
+                path,
+                value,
+                allowed.join(", ")
+            ));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != value {
+            problems.push(format!("{}: expected {}, got {}", path, expected, value));
+        }
+    }
+
+    match value {
+        Value::Object(object) => {
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for name in required.iter().filter_map(Value::as_str) {
+                    if !object.contains_key(name) {
+                        problems.push(format!("{}: missing required property \"{}\"", path, name));
+                    }
+                }
+            }
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (name, item) in object {
+                let item_path = format!("{}.{}", path, name);
+                match (
+                    properties.and_then(|properties| properties.get(name)),
+                    schema.get("additionalProperties"),
+                ) {
+                    (Some(property), _) => check(property, item, &item_path, problems),
+                    (None, Some(Value::Bool(false))) => {
+                        problems.push(format!("{}: unexpected property", item_path))
+                    }
+                    (None, Some(additional)) => check(additional, item, &item_path, problems),
+                    (None, None) => {}
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (index, item) in items.iter().enumerate() {
+                    check(item_schema, item, &format!("{}[{}]", path, index), problems);
+                }
+            }
+            let count = items.len() as f64;
+            if let Some(min) = limit(schema, "minItems").filter(|&min| count < min) {
+                problems.push(format!("{}: fewer than {} items", path, min));
+            }
+            if let Some(max) = limit(schema, "maxItems").filter(|&max| count > max) {
+                problems.push(format!("{}: more than {} items", path, max));
+            }
+        }
+        Value::String(text) => {
+            let length = text.chars().count() as f64;
+            if let Some(min) = limit(schema, "minLength").filter(|&min| length < min) {
+                problems.push(format!("{}: shorter than {} characters", path, min));
+            }
+            if let Some(max) = limit(schema, "maxLength").filter(|&max| length > max) {
+                problems.push(format!("{}: longer than {} characters", path, max));
+            }
+        }
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or_default();
+            let bounds: [(&str, Outside); 4] = [
+                ("minimum", |number, bound| number < bound),
+                ("maximum", |number, bound| number > bound),
+                ("exclusiveMinimum", |number, bound| number <= bound),
+                ("exclusiveMaximum", |number, bound| number >= bound),
+            ];
+            for (keyword, outside) in bounds {
+                if let Some(bound) = limit(schema, keyword).filter(|&bound| outside(number, bound))
+                {
+                    problems.push(format!(
+                        "{}: {} is outside the {} of {}",
+                        path, number, keyword, bound
+                    ));
+                }
+            }
+        }
+        _ => {}
+    }
+
+    let subschemas = |keyword: &str| {
+        schema
+            .get(keyword)
+            .and_then(Value::as_array)
+            .cloned()
+            .unwrap_or_default()
+    };
+    for subschema in subschemas("allOf") {
+        check(&subschema, value, path, problems);
+    }
+    let matching = |subschemas: &[Value]| {
+        subschemas
+            .iter()
+            .filter(|subschema| validate(subschema, value).is_empty())
+            .count()
+    };
+    let any_of = subschemas("anyOf");
+    if !any_of.is_empty() && matching(&any_of) == 0 {
+        problems.push(format!("{}: matches none of the anyOf schemas", path));
+    }
+    let one_of = subschemas("oneOf");
+    if !one_of.is_empty() && matching(&one_of) != 1 {
+        problems.push(format!(
+            "{}: matches {} of the oneOf schemas, not exactly one",
+            path,
+            matching(&one_of)
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn person() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "name": {"type": "string", "minLength": 1},
+                "age": {"type": "integer", "minimum": 0},
+                "tags": {"type": "array", "items": {"enum": ["a", "b"]}}
+            },
+            "required": ["name"],
+            "additionalProperties": false
+        })
+    }
+
+    #[test]
+    fn matching_values_have_no_problems() {
+        let value = json!({"name": "Ada", "age": 36, "tags": ["a"]});
+        assert!(validate(&person(), &value).is_empty());
+    }
+
+    #[test]
+    fn problems_name_the_path() {
+        let value = json!({"age": -1.5, "tags": ["c"], "extra": true});
+        let problems = validate(&person(), &value);
+        assert!(problems.contains(&"$: missing required property \"name\"".to_string()));
+        assert!(problems.contains(&"$.age: expected integer, got number".to_string()));
+        assert!(problems
+            .iter()
+            .any(|p| p.starts_with("$.tags[0]: \"c\" is not one of")));
+        assert!(problems.contains(&"$.extra: unexpected property".to_string()));
+    }
+
+    #[test]
+    fn combinators() {
+        let schema = json!({"oneOf": [{"type": "integer"}, {"type": "number"}]});
+        assert!(validate(&schema, &json!(1.5)).is_empty());
+        // An integer is a number too, so it matches both
+        assert_eq!(validate(&schema, &json!(2)).len(), 1);
+        let schema = json!({"anyOf": [{"type": "string"}, {"type": "null"}]});
+        assert!(validate(&schema, &json!(null)).is_empty());
+        assert_eq!(validate(&schema, &json!(1)).len(), 1);
+    }
+
+    #[test]
+    fn parse_takes_the_first_value() {
+        assert_eq!(parse("{\"a\": 1}\nHope that helps!"), Ok(json!({"a": 1})));
+        assert_eq!(parse("```json\n[1, 2]\n```"), Ok(json!([1, 2])));
+        assert!(parse("{\"a\":").is_err());
+    }
+}
@@ -0,0 +1,78 @@
+// `claude-cli search`: look through the saved history and sessions for turns whose /tag
+// labels or text contain the query, case-insensitively.
+use crate::config::load_history;
+use std::io;
+use std::path::PathBuf;
+
+/// A matching message and where it is
+pub struct Match {
+    pub path: PathBuf,
+    // Counted like the chat loop does: a user message and its reply make one turn
+    pub turn: usize,
+    pub role: String,
+    pub tags: Vec<String>,
+    pub snippet: String,
+}
+
+// Characters of context shown either side of a match
+const CONTEXT: usize = 60;
+
+pub fn search(paths: &[PathBuf], query: &str) -> io::Result<Vec<Match>> {
+    let query = query.to_lowercase();
+    let mut matches = Vec::new();
+    for path in paths {
+        for (index, message) in load_history(path)?.into_iter().enumerate() {
+            let text = message.content.text();
+            let tagged = message
+                .tags
+                .iter()
+                .any(|tag| tag.to_lowercase().contains(&query));
+            let found = find(&text, &query);
+            if !tagged && found.is_none() {
+                continue;
+            }
+            matches.push(Match {
+                path: path.clone(),
+                turn: index / 2 + 1,
+                role: message.role,
+                tags: message.tags,
+                snippet: snippet(&text, found.unwrap_or((0, 0))),
+            });
+        }
+    }
+    Ok(matches)
+}
+
+// Character offsets of the first case-insensitive occurrence. Lowercasing can change byte
+// lengths, so the search runs over characters.
+fn find(text: &str, query: &str) -> Option<(usize, usize)> {
+    let haystack: Vec<char> = text.to_lowercase().chars().collect();
+    let needle: Vec<char> = query.chars().collect();
+    if needle.is_empty() || haystack.len() != text.chars().count() {
+        // Some characters lowercase to several; fall back to a plain search
+        return text
+            .find(query)
+            .map(|start| (text[..start].chars().count(), query.chars().count()));
+    }
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle.as_slice())
+        .map(|start| (start, needle.len()))
+}
+
+// The match with some text around it, on one line
+fn snippet(text: &str, (start, len): (usize, usize)) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let from = start.saturating_sub(CONTEXT);
+    let to = (start + len + CONTEXT).min(chars.len());
+    let body: String = chars[from..to]
+        .iter()
+        .map(|&c| if c.is_whitespace() { ' ' } else { c })
+        .collect();
+    format!(
+        "{}{}{}",
+        if from > 0 { "..." } else { "" },
+        body.trim(),
+        if to < chars.len() { "..." } else { "" }
+    )
+}
@@ -0,0 +1,263 @@
+// The settings that shape every request, resolved once at startup. Each one takes the value
+// from the highest layer that has it: built-in default < config file (or its active profile)
+// < environment variable < command-line flag. Chat commands such as /model then change the
+// resolved settings directly.
+use crate::config::{Config, Source};
+use anthropic_in_a_shell::Settings;
+
+pub const MODEL_ENV: &str = "CLAUDE_CLI_MODEL";
+pub const MAX_TOKENS_ENV: &str = "CLAUDE_CLI_MAX_TOKENS";
+pub const SYSTEM_ENV: &str = "CLAUDE_CLI_SYSTEM";
+pub const TEMPERATURE_ENV: &str = "CLAUDE_CLI_TEMPERATURE";
+pub const TOP_P_ENV: &str = "CLAUDE_CLI_TOP_P";
+pub const USER_ID_ENV: &str = "CLAUDE_CLI_USER_ID";
+
+// The value of the highest layer that has one, and that layer; layers go lowest first
+fn highest<T>(layers: impl IntoIterator<Item = (Option<T>, Source)>) -> Option<(T, Source)> {
+    layers
+        .into_iter()
+        .filter_map(|(value, source)| value.map(|value| (value, source)))
+        .last()
+}
+
+/// What the command-line flags ask for; also where --persona puts its prompt
+#[derive(Default)]
+pub struct Flags {
+    pub model: Option<String>,
+    pub max_tokens: Option<u32>,
+    pub system: Option<String>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub user_id: Option<String>,
+}
+
+/// The layer each resolved setting came from, for `config show`; None where no layer has it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sources {
+    pub model: Source,
+    pub max_tokens: Source,
+    pub system: Option<Source>,
+    pub temperature: Option<Source>,
+    pub top_p: Option<Source>,
+    pub user_id: Option<Source>,
+}
+
+/// Layer the defaults, the config, the environment (looked up through `env`, so tests
+/// needn't touch the real one) and the flags. Fails on an environment variable that
+/// doesn't hold a valid value.
+pub fn resolve(
+    default_model: &str,
+    default_max_tokens: u32,
+    config: &Config,
+    env: impl Fn(&str) -> Option<String>,
+    flags: &Flags,
+) -> Result<(Settings, Sources), String> {
+    let file = |source: Option<Source>| source.unwrap_or(Source::File);
+    let number = |name: &str, valid: fn(f32) -> bool| -> Result<Option<f32>, String> {
+        env(name)
+            .map(|value| match value.trim().parse::<f32>() {
+                Ok(number) if valid(number) => Ok(number),
+                _ => Err(format!(
+                    "{}={} is not a number from 0.0 to 1.0",
+                    name, value
+                )),
+            })
+            .transpose()
+    };
+    let unit_interval = |number: f32| (0.0..=1.0).contains(&number);
+    let env_max_tokens = env(MAX_TOKENS_ENV)
+        .map(|value| match value.trim().parse::<u32>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(format!(
+                "{}={} is not a positive number",
+                MAX_TOKENS_ENV, value
+            )),
+        })
+        .transpose()?;
+
+    // Both have a default, so some layer always has them
+    let (model, model_source) = highest([
+        (Some(default_model.to_string()), Source::Default),
+        (
+            config.model().map(str::to_string),
+            file(config.model_source()),
+        ),
+        (env(MODEL_ENV), Source::Env),
+        (flags.model.clone(), Source::Flag),
+    ])
+    .expect("model has a default");
+    let (max_tokens, max_tokens_source) = highest([
+        (Some(default_max_tokens), Source::Default),
+        (config.max_tokens(), file(config.max_tokens_source())),
+        (env_max_tokens, Source::Env),
+        (flags.max_tokens, Source::Flag),
+    ])
+    .expect("max_tokens has a default");
+    let system = highest([
+        (
+            config.system().map(str::to_string),
+            file(config.system_source()),
+        ),
+        (env(SYSTEM_ENV), Source::Env),
+        (flags.system.clone(), Source::Flag),
+    ]);
+    let temperature = highest([
+        (config.temperature, Source::File),
+        (number(TEMPERATURE_ENV, unit_interval)?, Source::Env),
+        (flags.temperature, Source::Flag),
+    ]);
+    let top_p = highest([
+        (config.top_p, Source::File),
+        (number(TOP_P_ENV, unit_interval)?, Source::Env),
+        (flags.top_p, Source::Flag),
+    ]);
+    let user_id = highest([
+        (config.user_id.clone(), Source::File),
+        (env(USER_ID_ENV), Source::Env),
+        (flags.user_id.clone(), Source::Flag),
+    ]);
+    let sources = Sources {
+        model: model_source,
+        max_tokens: max_tokens_source,
+        system: system.as_ref().map(|(_, source)| *source),
+        temperature: temperature.map(|(_, source)| source),
+        top_p: top_p.map(|(_, source)| source),
+        user_id: user_id.as_ref().map(|(_, source)| *source),
+    };
+    let settings = Settings {
+        model,
+        max_tokens,
+        system: system.map(|(system, _)| system),
+        temperature: temperature.map(|(temperature, _)| temperature),
+        top_p: top_p.map(|(top_p, _)| top_p),
+        user_id: user_id.map(|(user_id, _)| user_id),
+        ..Settings::default()
+    };
+    Ok((settings, sources))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn config(json: &str) -> Config {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn layered(config: &Config, env: &[(&str, &str)], flags: &Flags) -> (Settings, Sources) {
+        let env: HashMap<String, String> = env
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        resolve(
+            "default-model",
+            1024,
+            config,
+            |name| env.get(name).cloned(),
+            flags,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn defaults_apply_when_nothing_is_set() {
+        let (settings, sources) = layered(&config("{}"), &[], &Flags::default());
+        assert_eq!(settings.model, "default-model");
+        assert_eq!(sources.model, Source::Default);
+        assert_eq!(settings.max_tokens, 1024);
+        assert_eq!(settings.system, None);
+        assert_eq!(settings.temperature, None);
+        assert_eq!(sources.temperature, None);
+    }
+
+    #[test]
+    fn config_file_beats_defaults() {
+        let (settings, sources) = layered(
+            &config(r#"{"model": "file-model", "max_tokens": 2000, "temperature": 0.5}"#),
+            &[],
+            &Flags::default(),
+        );
+        assert_eq!(settings.model, "file-model");
+        assert_eq!(sources.model, Source::File);
+        assert_eq!(settings.max_tokens, 2000);
+        assert_eq!(settings.temperature, Some(0.5));
+    }
+
+    #[test]
+    fn active_profile_beats_top_level_config() {
+        let (settings, sources) = layered(
+            &config(
+                r#"{"model": "file-model", "active_profile": "work",
+                    "profiles": {"work": {"model": "work-model"}}}"#,
+            ),
+            &[],
+            &Flags::default(),
+        );
+        assert_eq!(settings.model, "work-model");
+        assert_eq!(sources.model, Source::Profile);
+    }
+
+    #[test]
+    fn env_beats_config_file() {
+        let (settings, sources) = layered(
+            &config(r#"{"model": "file-model", "system": "from file"}"#),
+            &[(MODEL_ENV, "env-model"), (SYSTEM_ENV, "from env")],
+            &Flags::default(),
+        );
+        assert_eq!(settings.model, "env-model");
+        assert_eq!(sources.model, Source::Env);
+        assert_eq!(settings.system.as_deref(), Some("from env"));
+    }
+
+    #[test]
+    fn flags_beat_env() {
+        let flags = Flags {
+            model: Some("flag-model".to_string()),
+            temperature: Some(0.2),
+            user_id: Some("flag-user".to_string()),
+            ..Flags::default()
+        };
+        let (settings, sources) = layered(
+            &config(r#"{"model": "file-model"}"#),
+            &[
+                (MODEL_ENV, "env-model"),
+                (TEMPERATURE_ENV, "0.9"),
+                (USER_ID_ENV, "env-user"),
+            ],
+            &flags,
+        );
+        assert_eq!(settings.model, "flag-model");
+        assert_eq!(sources.model, Source::Flag);
+        assert_eq!(settings.temperature, Some(0.2));
+        assert_eq!(settings.user_id.as_deref(), Some("flag-user"));
+    }
+
+    #[test]
+    fn lower_layers_fill_in_what_higher_ones_leave_out() {
+        let flags = Flags {
+            max_tokens: Some(500),
+            ..Flags::default()
+        };
+        let (_, sources) = layered(
+            &config(r#"{"model": "file-model"}"#),
+            &[(TOP_P_ENV, "0.8")],
+            &flags,
+        );
+        assert_eq!(sources.model, Source::File);
+        assert_eq!(sources.max_tokens, Source::Flag);
+        assert_eq!(sources.top_p, Some(Source::Env));
+    }
+
+    #[test]
+    fn invalid_env_values_are_errors() {
+        let env = |value: &'static str| {
+            move |name: &str| (name == MAX_TOKENS_ENV).then(|| value.to_string())
+        };
+        let config = config("{}");
+        assert!(resolve("m", 1, &config, env("0"), &Flags::default()).is_err());
+        assert!(resolve("m", 1, &config, env("lots"), &Flags::default()).is_err());
+        let temperature = |name: &str| (name == TEMPERATURE_ENV).then(|| "1.5".to_string());
+        assert!(resolve("m", 1, &config, temperature, &Flags::default()).is_err());
+    }
+}
@@ -0,0 +1,55 @@
+// An animated spinner on stderr while waiting for the first part of a reply
+use std::io::{self, IsTerminal, Write};
+use std::time::Duration;
+
+const FRAMES: &[&str] = &["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+
+fn enabled() -> bool {
+    crate::color::enabled() && io::stderr().is_terminal()
+}
+
+/// Erase a spinner frame that may be showing, before printing a message to stderr
+pub fn clear_line() {
+    if enabled() {
+        let _ = write!(io::stderr(), "\r\x1b[K");
+    }
+}
+
+pub struct Spinner {
+    task: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl Spinner {
+    /// Start spinning, unless decoration is off or stderr is redirected and the animation
+    /// would be noise
+    pub fn start() -> Self {
+        if !enabled() {
+            return Spinner { task: None };
+        }
+        let task = tokio::spawn(async {
+            for frame in FRAMES.iter().cycle() {
+                let mut stderr = io::stderr();
+                let _ = write!(stderr, "\r{} ", frame);
+                let _ = stderr.flush();
+                tokio::time::sleep(Duration::from_millis(80)).await;
+            }
+        });
+        Spinner { task: Some(task) }
+    }
+
+    /// Stop and erase the spinner; safe to call more than once
+    pub fn stop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+            let mut stderr = io::stderr();
+            let _ = write!(stderr, "\r\x1b[K");
+            let _ = stderr.flush();
+        }
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,154 @@
+// `{{name}}` placeholders in prompts, filled from `--var name=value` and `/set name value`
+// before a message is sent. Names are letters, digits, `_`, `-` and `.`; anything else
+// between double braces is left alone, so code that uses them for its own purposes survives.
+use crate::modules::{InputContentBlock, MessageContent};
+use std::collections::BTreeMap;
+
+pub type Variables = BTreeMap<String, String>;
+
+/// Parse a `--var` argument of the form `name=value`
+pub fn parse_var(arg: &str) -> Result<(String, String), String> {
+    let (name, value) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("expected name=value, got '{}'", arg))?;
+    let name = name.trim();
+    if !is_name(name) {
+        return Err(format!(
+            "'{}' is not a valid variable name; use letters, digits, '_', '-' and '.'",
+            name
+        ));
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+pub fn is_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// Substitute every placeholder with a known value. Returns the result and the names of
+/// placeholders that had none, which are left in place.
+pub fn fill(text: &str, variables: &Variables) -> (String, Vec<String>) {
+    let mut output = String::with_capacity(text.len());
+    let mut unfilled = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            rest = &rest[start..];
+            break;
+        };
+        let name = after[..end].trim();
+        match variables.get(name) {
+            Some(value) if is_name(name) => output.push_str(value),
+            _ => {
+                if is_name(name) && !unfilled.iter().any(|n| n == name) {
+                    unfilled.push(name.to_string());
+                }
+                output.push_str(&rest[start..start + 2 + end + 2]);
+            }
+        }
+        rest = &after[end + 2..];
+    }
+    output.push_str(rest);
+    (output, unfilled)
+}
+
+/// `fill` over the text of a message, leaving images alone
+pub fn fill_content(
+    content: MessageContent,
+    variables: &Variables,
+) -> (MessageContent, Vec<String>) {
+    match content {
+        MessageContent::Text(text) => {
+            let (text, unfilled) = fill(&text, variables);
+            (MessageContent::Text(text), unfilled)
+        }
+        MessageContent::Blocks(blocks) => {
+            let mut all_unfilled: Vec<String> = Vec::new();
+            let blocks = blocks
+                .into_iter()
+                .map(|block| match block {
+                    InputContentBlock::Text {
+                        text,
+                        cache_control,
+                    } => {
+                        let (text, unfilled) = fill(&text, variables);
+                        for name in unfilled {
+                            if !all_unfilled.contains(&name) {
+                                all_unfilled.push(name);
+                            }
+                        }
+                        InputContentBlock::Text {
+                            text,
+                            cache_control,
+                        }
+                    }
+                    other => other,
+                })
+                .collect();
+            (MessageContent::Blocks(blocks), all_unfilled)
+        }
+    }
+}
+
+/// The error for placeholders left without a value
+pub fn unfilled_message(unfilled: &[String]) -> String {
+    let names = unfilled
+        .iter()
+        .map(|name| format!("{{{{{}}}}}", name))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "No value for {}. Give one with --var name=value or /set name value, or pass \
+         --allow-unfilled to send placeholders as written.",
+        names
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables() -> Variables {
+        Variables::from([
+            ("name".to_string(), "Ada".to_string()),
+            ("lang".to_string(), "Rust".to_string()),
+        ])
+    }
+
+    #[test]
+    fn fills_placeholders_with_values() {
+        let (text, unfilled) = fill("{{name}} writes {{ lang }}", &variables());
+        assert_eq!(text, "Ada writes Rust");
+        assert!(unfilled.is_empty());
+    }
+
+    #[test]
+    fn reports_each_unfilled_placeholder_once() {
+        let (text, unfilled) = fill("{{topic}}, {{name}} and {{topic}}", &variables());
+        assert_eq!(text, "{{topic}}, Ada and {{topic}}");
+        assert_eq!(unfilled, ["topic"]);
+        assert!(unfilled_message(&unfilled).starts_with("No value for {{topic}}."));
+    }
+
+    #[test]
+    fn leaves_other_braces_alone() {
+        for text in ["{{not a name}}", "open {{name", "{{}}"] {
+            assert_eq!(fill(text, &variables()), (text.to_string(), Vec::new()));
+        }
+    }
+
+    #[test]
+    fn parses_var_flags() {
+        assert_eq!(
+            parse_var(" topic =a=b"),
+            Ok(("topic".to_string(), "a=b".to_string()))
+        );
+        assert!(parse_var("topic").is_err());
+        assert!(parse_var("bad name=1").is_err());
+    }
+}
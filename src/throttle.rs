@@ -0,0 +1,44 @@
+//! A client-side rate limit, which the command line tool sets with --rps and --min-interval:
+//! requests go out no closer together than the interval, however many are in flight at once.
+//! It works as a token bucket that holds a single token and gets it back once per interval, so
+//! there are no bursts either.
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// The longest wait between requests; anything slower is held to it
+pub const MAX_INTERVAL: Duration = Duration::from_secs(86_400);
+
+pub struct Throttle {
+    interval: Duration,
+    // When the next request may go; None until the first one has
+    next: Mutex<Option<Instant>>,
+}
+
+impl Throttle {
+    pub fn new(interval: Duration) -> Self {
+        Throttle {
+            interval,
+            next: Mutex::new(None),
+        }
+    }
+
+    /// Rates below one request a day are held to that
+    pub fn per_second(requests: f64) -> Self {
+        let seconds = (1.0 / requests).min(MAX_INTERVAL.as_secs_f64());
+        Throttle::new(Duration::try_from_secs_f64(seconds).unwrap_or(MAX_INTERVAL))
+    }
+
+    /// Wait for this request's turn. The lock is fair, so waiting requests go in the order
+    /// they arrived.
+    pub async fn wait(&self) {
+        let mut next = self.next.lock().await;
+        if let Some(at) = *next {
+            if at > Instant::now() {
+                log::debug!("Throttled for {:.2?}", at - Instant::now());
+                tokio::time::sleep_until(at).await;
+            }
+        }
+        *next = Some(Instant::now() + self.interval);
+    }
+}
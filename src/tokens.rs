@@ -0,0 +1,98 @@
+// Rough token counting, for noticing a conversation outgrowing the context window before
+// the API rejects it. Claude's tokenizer isn't public; about four characters per token is
+// close enough for English prose and code.
+use crate::modules::{ClaudeMessage, MessageContent};
+
+const CHARS_PER_TOKEN: usize = 4;
+// Role markers and separators the API adds around each message
+const MESSAGE_OVERHEAD: usize = 4;
+// Images are resized to about 1.15 megapixels at most, roughly 1600 tokens
+const IMAGE_TOKENS: usize = 1600;
+
+/// Default warning threshold, comfortably below the 200k token context of current models
+pub const DEFAULT_WARN_TOKENS: u32 = 150_000;
+
+/// Default size of a single message above which sending it needs confirming
+pub const DEFAULT_CONFIRM_TOKENS: u32 = 25_000;
+
+/// Default budget for auto-trim, leaving room under a 200k context for the reply
+pub const DEFAULT_CONTEXT_LIMIT: u32 = 180_000;
+
+fn estimate_text(text: &str) -> usize {
+    text.chars().count().div_ceil(CHARS_PER_TOKEN)
+}
+
+/// Estimated tokens for one message's text and images
+pub fn estimate_content(content: &MessageContent) -> usize {
+    estimate_text(&content.text()) + content.image_count() * IMAGE_TOKENS + MESSAGE_OVERHEAD
+}
+
+/// Estimated input tokens for sending `messages` with the given system prompt
+pub fn estimate(messages: &[ClaudeMessage], system: Option<&str>) -> usize {
+    let system_tokens = system.map_or(0, estimate_text);
+    let message_tokens: usize = messages
+        .iter()
+        .map(|message| estimate_content(&message.content))
+        .sum();
+    system_tokens + message_tokens
+}
+
+/// What to trim to once the conversation reaches `threshold`: enough below it that the
+/// next few exchanges fit without asking again
+pub fn trim_target(threshold: usize) -> usize {
+    threshold / 4 * 3
+}
+
+/// Drop the oldest exchanges until the estimate fits in `limit`, always keeping the latest
+/// one. Returns how many exchanges were removed.
+pub fn trim_oldest(messages: &mut Vec<ClaudeMessage>, system: Option<&str>, limit: usize) -> usize {
+    let mut removed = 0;
+    // Whole exchanges go together, including any tool calls, so the history still starts
+    // with a turn the user wrote
+    while estimate(messages, system) > limit {
+        let Some(next) = messages
+            .iter()
+            .skip(1)
+            .position(|message| message.role == "user" && !message.content.is_tool_result())
+        else {
+            break;
+        };
+        messages.drain(..=next);
+        removed += 1;
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn turn(role: &str, text: &str) -> ClaudeMessage {
+        ClaudeMessage {
+            role: role.to_string(),
+            content: text.to_string().into(),
+            tags: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn trims_whole_exchanges_oldest_first() {
+        // Each turn is 100 characters: 25 tokens plus 4 overhead
+        let text = "x".repeat(100);
+        let mut messages: Vec<ClaudeMessage> = (0..4)
+            .flat_map(|_| [turn("user", &text), turn("assistant", &text)])
+            .collect();
+        assert_eq!(estimate(&messages, None), 232);
+        assert_eq!(trim_oldest(&mut messages, None, 120), 2);
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[0].role, "user");
+        // The latest exchange always stays, even over the limit
+        assert_eq!(trim_oldest(&mut messages, None, 10), 1);
+        assert_eq!(messages.len(), 2);
+    }
+
+    #[test]
+    fn trimming_leaves_room_below_the_threshold() {
+        assert_eq!(trim_target(150_000), 112_500);
+    }
+}
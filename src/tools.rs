@@ -0,0 +1,243 @@
+// Local tools Claude can call during a reply. Each tool pairs the definition sent with the
+// request with a handler that runs here; the handler's output goes back as a tool_result.
+use crate::modules::Tool;
+use serde_json::{json, Value};
+
+/// Runs a tool on the input Claude chose, returning the text to hand back
+pub type Handler = fn(&Value) -> Result<String, String>;
+
+pub use anthropic_in_a_shell::client::ToolCall;
+
+#[derive(Default)]
+pub struct Registry {
+    tools: Vec<(Tool, Handler)>,
+}
+
+impl Registry {
+    /// The tools that ship with the program
+    pub fn builtin() -> Self {
+        let mut registry = Registry::default();
+        registry.register(
+            Tool {
+                name: "calculator".to_string(),
+                description: "Evaluate an arithmetic expression exactly. Supports + - * / % ^ \
+                              and parentheses. Use it for any calculation rather than doing \
+                              the arithmetic yourself."
+                    .to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "expression": {
+                            "type": "string",
+                            "description": "The expression to evaluate, e.g. (2 + 3) * 4 ^ 2"
+                        }
+                    },
+                    "required": ["expression"]
+                }),
+            },
+            calculator,
+        );
+        registry
+    }
+
+    pub fn register(&mut self, tool: Tool, handler: Handler) {
+        self.tools.push((tool, handler));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    /// Definitions for the request's `tools` array
+    pub fn definitions(&self) -> Vec<Tool> {
+        self.tools.iter().map(|(tool, _)| tool.clone()).collect()
+    }
+
+    /// Run the call's handler, returning its output and whether it failed
+    pub fn call(&self, call: &ToolCall) -> (String, bool) {
+        let result = match self.tools.iter().find(|(tool, _)| tool.name == call.name) {
+            Some((_, handler)) => handler(&call.input),
+            None => Err(format!("unknown tool '{}'", call.name)),
+        };
+        match result {
+            Ok(output) => (output, false),
+            Err(e) => (e, true),
+        }
+    }
+}
+
+fn calculator(input: &Value) -> Result<String, String> {
+    let expression = input
+        .get("expression")
+        .and_then(Value::as_str)
+        .ok_or("missing the 'expression' string")?;
+    let mut parser = Calculator {
+        chars: expression.chars().filter(|c| !c.is_whitespace()).collect(),
+        pos: 0,
+    };
+    let value = parser.expression()?;
+    if parser.pos < parser.chars.len() {
+        return Err(format!(
+            "unexpected '{}' in {}",
+            parser.chars[parser.pos], expression
+        ));
+    }
+    if !value.is_finite() {
+        return Err(format!("{} does not have a finite value", expression));
+    }
+    // Whole numbers print without a trailing .0
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        Ok(format!("{}", value as i64))
+    } else {
+        Ok(value.to_string())
+    }
+}
+
+// Recursive descent, lowest precedence first; ^ is right associative and binds tighter
+// than a leading minus, so -2^2 is -4
+struct Calculator {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Calculator {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expression(&mut self) -> Result<f64, String> {
+        let mut value = self.term()?;
+        loop {
+            if self.eat('+') {
+                value += self.term()?;
+            } else if self.eat('-') {
+                value -= self.term()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<f64, String> {
+        let mut value = self.unary()?;
+        loop {
+            if self.eat('*') {
+                value *= self.unary()?;
+            } else if self.eat('/') {
+                let divisor = self.unary()?;
+                if divisor == 0.0 {
+                    return Err("division by zero".to_string());
+                }
+                value /= divisor;
+            } else if self.eat('%') {
+                let divisor = self.unary()?;
+                if divisor == 0.0 {
+                    return Err("division by zero".to_string());
+                }
+                value %= divisor;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<f64, String> {
+        if self.eat('-') {
+            Ok(-self.unary()?)
+        } else if self.eat('+') {
+            self.unary()
+        } else {
+            self.power()
+        }
+    }
+
+    fn power(&mut self) -> Result<f64, String> {
+        let base = self.primary()?;
+        if self.eat('^') {
+            Ok(base.powf(self.unary()?))
+        } else {
+            Ok(base)
+        }
+    }
+
+    fn primary(&mut self) -> Result<f64, String> {
+        if self.eat('(') {
+            let value = self.expression()?;
+            if !self.eat(')') {
+                return Err("missing ')'".to_string());
+            }
+            return Ok(value);
+        }
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(match self.peek() {
+                Some(c) => format!("expected a number, found '{}'", c),
+                None => "expected a number at the end".to_string(),
+            });
+        }
+        let number: String = self.chars[start..self.pos].iter().collect();
+        number
+            .parse()
+            .map_err(|_| format!("'{}' is not a number", number))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, input: Value) -> (String, bool) {
+        Registry::builtin().call(&ToolCall {
+            id: "toolu_1".to_string(),
+            name: name.to_string(),
+            input,
+        })
+    }
+
+    fn calculate(expression: &str) -> Result<String, String> {
+        calculator(&json!({ "expression": expression }))
+    }
+
+    #[test]
+    fn calculator_follows_precedence() {
+        assert_eq!(calculate("1 + 2 * 3").unwrap(), "7");
+        assert_eq!(calculate("(1 + 2) * 3").unwrap(), "9");
+        assert_eq!(calculate("10 - 4 - 3").unwrap(), "3");
+        assert_eq!(calculate("7 % 4 + 1").unwrap(), "4");
+        // ^ is right associative and binds tighter than a leading minus
+        assert_eq!(calculate("2 ^ 3 ^ 2").unwrap(), "512");
+        assert_eq!(calculate("-2^2").unwrap(), "-4");
+        assert_eq!(calculate("1 / 4").unwrap(), "0.25");
+    }
+
+    #[test]
+    fn calculator_errors_go_back_to_claude() {
+        assert_eq!(
+            call("calculator", json!({ "expression": "1 / 0" })),
+            ("division by zero".to_string(), true)
+        );
+        assert_eq!(calculate("(1 + 2").unwrap_err(), "missing ')'");
+        assert_eq!(calculate("2 x").unwrap_err(), "unexpected 'x' in 2 x");
+        assert_eq!(
+            calculate("10 ^ 400").unwrap_err(),
+            "10 ^ 400 does not have a finite value"
+        );
+        assert!(call("calculator", json!({})).1);
+        assert_eq!(
+            call("shell", json!({})),
+            ("unknown tool 'shell'".to_string(), true)
+        );
+    }
+}
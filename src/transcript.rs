@@ -0,0 +1,339 @@
+// Writing the conversation out to files the user can keep or share
+use crate::modules::{ClaudeMessage, InputContentBlock, MessageContent};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn speaker(role: &str) -> &'static str {
+    if role == "user" {
+        "You"
+    } else {
+        "Claude"
+    }
+}
+
+pub fn to_markdown(messages: &[ClaudeMessage]) -> String {
+    let mut output = String::new();
+    for message in messages {
+        output.push_str(&format!("**{}:**\n\n", speaker(&message.role)));
+        for _ in 0..message.content.image_count() {
+            output.push_str("*[image]*\n\n");
+        }
+        let text = message.content.text();
+        if !text.is_empty() {
+            output.push_str(&format!("{}\n\n", text));
+        }
+        if let MessageContent::Blocks(blocks) = &message.content {
+            for block in blocks {
+                match block {
+                    InputContentBlock::ToolUse { name, input, .. } => {
+                        output.push_str(&format!("*[{} {}]*\n\n", name, input))
+                    }
+                    InputContentBlock::ToolResult { content, .. } => {
+                        output.push_str(&format!("*[result: {}]*\n\n", content))
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    output
+}
+
+const HTML_STYLE: &str = "
+body { font-family: -apple-system, 'Segoe UI', Helvetica, Arial, sans-serif; line-height: 1.5;
+       max-width: 48rem; margin: 2rem auto; padding: 0 1rem; color: #1f2328; }
+.turn { margin-bottom: 1.5rem; padding: 0.75rem 1rem; border-radius: 8px; }
+.user { background: #eef4ff; }
+.assistant { background: #f6f8fa; }
+.speaker { font-weight: 600; margin-bottom: 0.25rem; }
+.note { color: #656d76; font-style: italic; }
+code { font-family: ui-monospace, Menlo, Consolas, monospace; font-size: 0.9em;
+       background: #e8eaed; padding: 0.1em 0.3em; border-radius: 4px; }
+pre { background: #1f2328; color: #e6edf3; padding: 0.75rem; border-radius: 6px; overflow-x: auto; }
+pre code { background: none; padding: 0; color: inherit; }
+";
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// `code` and **bold** within a line; everything else is escaped as is
+fn inline_html(line: &str) -> String {
+    let mut output = String::new();
+    let parts: Vec<&str> = line.split('`').collect();
+    for (i, part) in parts.iter().enumerate() {
+        // Odd parts sit between backticks, unless the last backtick has no partner
+        if i % 2 == 1 && i < parts.len() - 1 {
+            output.push_str(&format!("<code>{}</code>", escape_html(part)));
+            continue;
+        }
+        if i % 2 == 1 {
+            output.push('`');
+        }
+        let pieces: Vec<&str> = part.split("**").collect();
+        for (j, piece) in pieces.iter().enumerate() {
+            let escaped = escape_html(piece);
+            if j % 2 == 1 && j < pieces.len() - 1 {
+                output.push_str(&format!("<strong>{}</strong>", escaped));
+            } else {
+                if j % 2 == 1 {
+                    output.push_str("**");
+                }
+                output.push_str(&escaped);
+            }
+        }
+    }
+    output
+}
+
+// Paragraphs and fenced code blocks; a fence left open runs to the end of the text
+fn text_to_html(text: &str) -> String {
+    let mut output = String::new();
+    let mut paragraph: Vec<String> = Vec::new();
+    let mut code: Option<Vec<&str>> = None;
+    let flush = |paragraph: &mut Vec<String>, output: &mut String| {
+        if !paragraph.is_empty() {
+            output.push_str(&format!("<p>{}</p>\n", paragraph.join("<br>\n")));
+            paragraph.clear();
+        }
+    };
+    for line in text.lines() {
+        let fence = line.trim_start().strip_prefix("```");
+        match (&mut code, fence) {
+            (Some(lines), Some(_)) => {
+                output.push_str(&format!(
+                    "<pre><code>{}</code></pre>\n",
+                    escape_html(&lines.join("\n"))
+                ));
+                code = None;
+            }
+            (Some(lines), None) => lines.push(line),
+            (None, Some(language)) => {
+                flush(&mut paragraph, &mut output);
+                let language = language.trim();
+                if !language.is_empty() {
+                    output.push_str(&format!(
+                        "<div class=\"note\">{}</div>\n",
+                        escape_html(language)
+                    ));
+                }
+                code = Some(Vec::new());
+            }
+            (None, None) if line.trim().is_empty() => flush(&mut paragraph, &mut output),
+            (None, None) => paragraph.push(inline_html(line)),
+        }
+    }
+    flush(&mut paragraph, &mut output);
+    if let Some(lines) = code {
+        output.push_str(&format!(
+            "<pre><code>{}</code></pre>\n",
+            escape_html(&lines.join("\n"))
+        ));
+    }
+    output
+}
+
+/// A self-contained page with the conversation, styles included, for sharing
+pub fn to_html(messages: &[ClaudeMessage]) -> String {
+    let mut output = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Conversation with Claude</title>\n<style>{}</style>\n</head>\n<body>\n",
+        HTML_STYLE
+    );
+    for message in messages {
+        let role = if message.role == "user" {
+            "user"
+        } else {
+            "assistant"
+        };
+        output.push_str(&format!(
+            "<div class=\"turn {}\">\n<div class=\"speaker\">{}</div>\n",
+            role,
+            speaker(&message.role)
+        ));
+        for _ in 0..message.content.image_count() {
+            output.push_str("<p class=\"note\">[image]</p>\n");
+        }
+        output.push_str(&text_to_html(&message.content.text()));
+        if let MessageContent::Blocks(blocks) = &message.content {
+            for block in blocks {
+                let note = match block {
+                    InputContentBlock::ToolUse { name, input, .. } => {
+                        format!("[{} {}]", name, input)
+                    }
+                    InputContentBlock::ToolResult { content, .. } => {
+                        format!("[result: {}]", content)
+                    }
+                    _ => continue,
+                };
+                output.push_str(&format!("<p class=\"note\">{}</p>\n", escape_html(&note)));
+            }
+        }
+        output.push_str("</div>\n");
+    }
+    output.push_str("</body>\n</html>\n");
+    output
+}
+
+/// Write the conversation as JSON for `.json` paths, HTML for `.html`, Markdown otherwise.
+/// Returns the absolute path written.
+pub fn save(path: &Path, messages: &[ClaudeMessage]) -> io::Result<PathBuf> {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_ascii_lowercase());
+    let contents = match extension.as_ref().and_then(|extension| extension.to_str()) {
+        Some("json") => serde_json::to_string_pretty(messages)?,
+        Some("html" | "htm") => to_html(messages),
+        _ => to_markdown(messages),
+    };
+    fs::write(path, contents)?;
+    std::path::absolute(path)
+}
+
+/// Write the conversation as HTML whatever the path's extension
+pub fn export_html(path: &Path, messages: &[ClaudeMessage]) -> io::Result<PathBuf> {
+    fs::write(path, to_html(messages))?;
+    std::path::absolute(path)
+}
+
+/// Write one reply as it came from Claude, Markdown and all, without the chat decoration
+pub fn save_reply(path: &Path, text: &str) -> io::Result<PathBuf> {
+    let mut contents = text.to_string();
+    if !contents.ends_with('\n') {
+        contents.push('\n');
+    }
+    fs::write(path, contents)?;
+    std::path::absolute(path)
+}
+
+/// Read a conversation saved as JSON, checking that it is a valid alternating history
+pub fn load(path: &Path) -> io::Result<Vec<ClaudeMessage>> {
+    let contents = fs::read_to_string(path)?;
+    let messages: Vec<ClaudeMessage> = serde_json::from_str(&contents)?;
+    validate_turns(&messages).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(messages)
+}
+
+// The API expects user/assistant turns alternating, starting with the user
+fn validate_turns(messages: &[ClaudeMessage]) -> Result<(), String> {
+    for (i, message) in messages.iter().enumerate() {
+        let expected = if i % 2 == 0 { "user" } else { "assistant" };
+        if message.role != expected {
+            return Err(format!(
+                "message {} has role '{}', expected '{}'",
+                i + 1,
+                message.role,
+                expected
+            ));
+        }
+    }
+    if messages.len() % 2 == 1 {
+        return Err("the last user message has no reply".to_string());
+    }
+    Ok(())
+}
+
+/// Replies copied to a file as they arrive, for `--out`. Each write is flushed so a crash
+/// still leaves what was generated so far.
+pub struct ReplyFile {
+    file: fs::File,
+    // Whether anything has been written, and whether a new turn has begun since
+    written: bool,
+    new_turn: bool,
+}
+
+impl ReplyFile {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(ReplyFile {
+            file: fs::File::create(path)?,
+            written: false,
+            new_turn: false,
+        })
+    }
+
+    /// Separate whatever comes next from the previous reply
+    pub fn start_turn(&mut self) {
+        self.new_turn = true;
+    }
+
+    pub fn write(&mut self, text: &str) -> io::Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        if self.new_turn && self.written {
+            self.file.write_all(b"\n\n---\n\n")?;
+        }
+        self.new_turn = false;
+        self.written = true;
+        self.file.write_all(text.as_bytes())?;
+        self.file.flush()
+    }
+}
+
+/// A filename like `conversation-20241022-153000.md` for when no path is given
+pub fn default_filename(extension: &str) -> String {
+    format!("conversation-{}.{}", timestamp(), extension)
+}
+
+// UTC `YYYYMMDD-HHMMSS` for the current time
+fn timestamp() -> String {
+    let (year, month, day, hour, minute, second) = now_utc();
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year, month, day, hour, minute, second
+    )
+}
+
+/// The current UTC time in RFC 3339, e.g. `2024-10-22T15:30:00Z`
+pub fn rfc3339_now() -> String {
+    let (year, month, day, hour, minute, second) = now_utc();
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, hour, minute, second
+    )
+}
+
+fn now_utc() -> (i64, u32, u32, u64, u64, u64) {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    let time_of_day = seconds % 86_400;
+    (
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day % 3600 / 60,
+        time_of_day % 60,
+    )
+}
+
+// Days since 1970-01-01 to a (year, month, day) date, from Howard Hinnant's algorithm
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,74 @@
+// Paced output for `--typing-speed`: streamed text is queued and let out at a steady rate
+// instead of in bursts. The queue is emptied at once when the reply ends, so pacing never
+// holds up the answer.
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// Faster rates print several characters per tick rather than waking up more often
+const MIN_TICK: Duration = Duration::from_millis(10);
+
+pub struct Typewriter {
+    queue: VecDeque<char>,
+    tick: Duration,
+    chars_per_tick: usize,
+}
+
+impl Typewriter {
+    /// Print about `cps` characters per second
+    pub fn new(cps: u32) -> Self {
+        let cps = cps.max(1);
+        let per_char = Duration::from_secs(1) / cps;
+        let (tick, chars_per_tick) = if per_char >= MIN_TICK {
+            (per_char, 1)
+        } else {
+            (MIN_TICK, (cps as usize).div_ceil(100))
+        };
+        Typewriter {
+            queue: VecDeque::new(),
+            tick,
+            chars_per_tick,
+        }
+    }
+
+    pub fn push(&mut self, text: &str) {
+        self.queue.extend(text.chars());
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// How long to wait before printing the next piece
+    pub fn tick(&self) -> Duration {
+        self.tick
+    }
+
+    /// The next piece to print. Escape sequences come out whole and don't count as
+    /// characters, so styling doesn't slow the text down.
+    pub fn next(&mut self) -> String {
+        let mut output = String::new();
+        let mut printed = 0;
+        while printed < self.chars_per_tick {
+            let Some(c) = self.queue.pop_front() else {
+                break;
+            };
+            output.push(c);
+            if c == '\x1b' {
+                while let Some(c) = self.queue.pop_front() {
+                    output.push(c);
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+                continue;
+            }
+            printed += 1;
+        }
+        output
+    }
+
+    /// Everything still queued
+    pub fn drain(&mut self) -> String {
+        self.queue.drain(..).collect()
+    }
+}
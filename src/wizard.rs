@@ -0,0 +1,115 @@
+// First-run setup: asks for the API key, model, reply length and temperature, then saves
+// them. Runs by itself the first time chat mode starts at a terminal, and again with `init`.
+use crate::config::Config;
+use crate::models::KNOWN_MODELS;
+use crate::readline::{Editor, ReadResult};
+use std::io;
+
+/// Ask the questions and save the answers; nothing is saved if the user bails out with
+/// Ctrl-C or Ctrl-D
+pub fn run(config: &mut Config, default_model: &str, default_max_tokens: u32) -> io::Result<()> {
+    let mut editor = Editor::new();
+    println!("Let's set up claude-cli. Press Enter to keep the value in [brackets].");
+
+    let key = if config.api_key().is_some() {
+        println!("\nAn API key is already set; run `claude-cli setkey <key>` to change it.");
+        None
+    } else {
+        println!("\nCreate an API key at https://console.anthropic.com/settings/keys");
+        loop {
+            let Some(answer) = ask(&mut editor, "API key: ")? else {
+                return cancelled();
+            };
+            if !answer.is_empty() {
+                break Some(answer);
+            }
+            println!("An API key is needed to talk to Claude.");
+        }
+    };
+
+    let current_model = config.model().unwrap_or(default_model).to_string();
+    println!("\nModels:");
+    for (i, (id, description)) in KNOWN_MODELS.iter().enumerate() {
+        println!("  {:>2}. {:<28} {}", i + 1, id, description);
+    }
+    let default_choice = KNOWN_MODELS
+        .iter()
+        .position(|(id, _)| *id == current_model)
+        .map(|i| (i + 1).to_string())
+        .unwrap_or_else(|| current_model.clone());
+    let model = loop {
+        let Some(answer) = ask(&mut editor, &format!("Model [{}]: ", default_choice))? else {
+            return cancelled();
+        };
+        if answer.is_empty() {
+            break current_model.clone();
+        }
+        // A number from the list, or any model id typed out
+        match answer.parse::<usize>() {
+            Ok(n) if (1..=KNOWN_MODELS.len()).contains(&n) => {
+                break KNOWN_MODELS[n - 1].0.to_string()
+            }
+            Ok(_) => println!("Pick a number from 1 to {}.", KNOWN_MODELS.len()),
+            Err(_) => break answer,
+        }
+    };
+
+    let current_max_tokens = config.max_tokens().unwrap_or(default_max_tokens);
+    let max_tokens = loop {
+        let prompt = format!("Maximum tokens per reply [{}]: ", current_max_tokens);
+        let Some(answer) = ask(&mut editor, &prompt)? else {
+            return cancelled();
+        };
+        if answer.is_empty() {
+            break current_max_tokens;
+        }
+        match answer.parse::<u32>() {
+            Ok(n) if n > 0 => break n,
+            _ => println!("Enter a positive whole number."),
+        }
+    };
+
+    let current_temperature = config
+        .temperature
+        .map_or_else(|| "API default".to_string(), |t| t.to_string());
+    let temperature = loop {
+        let prompt = format!("Temperature, 0.0 to 1.0 [{}]: ", current_temperature);
+        let Some(answer) = ask(&mut editor, &prompt)? else {
+            return cancelled();
+        };
+        if answer.is_empty() {
+            break None;
+        }
+        match answer.parse::<f32>() {
+            Ok(t) if (0.0..=1.0).contains(&t) => break Some(t),
+            _ => println!("Enter a number between 0.0 and 1.0."),
+        }
+    };
+
+    if let Some(key) = key {
+        config.set_key(key)?;
+    }
+    config.set_model(model)?;
+    config.set_max_tokens(max_tokens)?;
+    if let Some(temperature) = temperature {
+        config.set_temperature(temperature)?;
+    }
+    println!(
+        "\nSaved to {}. Run `claude-cli init` to change these later.\n",
+        config.path().display()
+    );
+    Ok(())
+}
+
+// The trimmed answer, or None when the user gives up
+fn ask(editor: &mut Editor, prompt: &str) -> io::Result<Option<String>> {
+    match editor.readline(prompt)? {
+        ReadResult::Line(answer) => Ok(Some(answer.trim().to_string())),
+        ReadResult::Interrupted | ReadResult::Eof => Ok(None),
+    }
+}
+
+fn cancelled() -> io::Result<()> {
+    println!("\nSetup cancelled; nothing was saved.");
+    Ok(())
+}
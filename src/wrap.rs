@@ -0,0 +1,231 @@
+// Word wrapping for replies printed to a terminal. Works on streamed output that may
+// already carry ANSI styles, holding back at most one word, and leaves the lines of fenced
+// code blocks alone so code is never broken mid-line.
+
+// Columns left free on the right so wrapped text doesn't touch the edge
+const MARGIN: usize = 2;
+
+// Columns and rows of the terminal on stdout, if it is one
+#[cfg(unix)]
+fn window_size() -> Option<(usize, usize)> {
+    // SAFETY: TIOCGWINSZ only fills in the winsize struct passed to it
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0
+        && size.ws_col > 0
+    {
+        return Some((size.ws_col as usize, size.ws_row as usize));
+    }
+    None
+}
+
+#[cfg(not(unix))]
+fn window_size() -> Option<(usize, usize)> {
+    None
+}
+
+fn env_size(name: &str) -> Option<usize> {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .filter(|&value| value > 0)
+}
+
+/// The width to wrap stdout at, from the terminal itself or else $COLUMNS
+pub fn terminal_width() -> Option<usize> {
+    window_size()
+        .map(|(columns, _)| columns)
+        .or_else(|| env_size("COLUMNS"))
+}
+
+/// Rows on screen, from the terminal itself or else $LINES
+pub fn terminal_height() -> Option<usize> {
+    window_size()
+        .map(|(_, rows)| rows)
+        .filter(|&rows| rows > 0)
+        .or_else(|| env_size("LINES"))
+}
+
+/// Column to wrap at for a terminal of this width
+pub fn wrap_width(columns: usize) -> usize {
+    columns.saturating_sub(MARGIN).max(20)
+}
+
+// Emoji and East Asian wide characters take two columns; this covers the common ranges
+pub fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x1100..=0x115F
+        | 0x2E80..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1FAFF
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+/// Columns taken by plain text such as a prompt prefix
+pub fn text_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+/// Rewraps text as it streams through, breaking lines at spaces
+pub struct Wrapper {
+    width: usize,
+    column: usize,
+    // The word being read, escape codes included, and how many columns it takes
+    word: String,
+    word_width: usize,
+    // Spaces seen since the last word, printed only if the next word fits on the line
+    spaces: String,
+    // Visible text of the current line, to spot code fences
+    line: String,
+    in_code_block: bool,
+    in_escape: bool,
+}
+
+impl Wrapper {
+    /// Wrap at `width` columns, with `column` already used on the first line (e.g. by the
+    /// reply prefix)
+    pub fn new(width: usize, column: usize) -> Self {
+        Wrapper {
+            width,
+            column,
+            word: String::new(),
+            word_width: 0,
+            spaces: String::new(),
+            line: String::new(),
+            in_code_block: false,
+            in_escape: false,
+        }
+    }
+
+    /// Feed more output, returning what can be printed now
+    pub fn push(&mut self, text: &str) -> String {
+        let mut output = String::new();
+        for c in text.chars() {
+            // Styles stay attached to the word they apply to; in code they go straight out
+            if self.in_escape || c == '\x1b' {
+                if self.in_code_block {
+                    output.push(c);
+                } else {
+                    self.word.push(c);
+                }
+                // CSI sequences end with a letter
+                self.in_escape = c == '\x1b' || !c.is_ascii_alphabetic();
+                continue;
+            }
+            match c {
+                '\n' => {
+                    self.flush_word(&mut output);
+                    self.spaces.clear();
+                    output.push('\n');
+                    if self.line.trim_start().starts_with("```") {
+                        self.in_code_block = !self.in_code_block;
+                    }
+                    self.line.clear();
+                    self.column = 0;
+                }
+                _ if self.in_code_block => {
+                    output.push(c);
+                    self.line.push(c);
+                }
+                ' ' | '\t' => {
+                    self.flush_word(&mut output);
+                    self.spaces.push(c);
+                    self.line.push(c);
+                }
+                _ => {
+                    self.word.push(c);
+                    self.word_width += char_width(c);
+                    self.line.push(c);
+                }
+            }
+        }
+        output
+    }
+
+    /// Whatever is still held back at the end of the reply
+    pub fn finish(&mut self) -> String {
+        let mut output = String::new();
+        self.flush_word(&mut output);
+        output
+    }
+
+    fn flush_word(&mut self, output: &mut String) {
+        if self.word.is_empty() {
+            return;
+        }
+        let spaces_width = self.spaces.chars().count();
+        // Leading indentation stays; a word that can't fit anywhere is left long
+        if self.column > 0 && self.column + spaces_width + self.word_width > self.width {
+            output.push('\n');
+            self.column = 0;
+        } else {
+            output.push_str(&self.spaces);
+            self.column += spaces_width;
+        }
+        self.spaces.clear();
+        output.push_str(&self.word);
+        self.column += self.word_width;
+        self.word.clear();
+        self.word_width = 0;
+    }
+}
+
+/// Wrap complete text in one go
+pub fn wrap(text: &str, width: usize, column: usize) -> String {
+    let mut wrapper = Wrapper::new(width, column);
+    let mut output = wrapper.push(text);
+    output.push_str(&wrapper.finish());
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breaks_lines_at_spaces() {
+        assert_eq!(wrap("the quick brown fox", 10, 0), "the quick\nbrown fox");
+        // The reply prefix already took part of the first line
+        assert_eq!(wrap("hello world", 10, 5), "hello\nworld");
+        // A word longer than the line is left whole
+        assert_eq!(
+            wrap("a supercalifragilistic b", 10, 0),
+            "a\nsupercalifragilistic\nb"
+        );
+    }
+
+    #[test]
+    fn keeps_newlines_and_code_blocks() {
+        assert_eq!(wrap("aaaa\nbbbb cc", 8, 0), "aaaa\nbbbb cc");
+        let reply = "```\nlet code = never_wrapped();\n```\nback to words";
+        assert_eq!(
+            wrap(reply, 8, 0),
+            "```\nlet code = never_wrapped();\n```\nback to\nwords"
+        );
+    }
+
+    #[test]
+    fn counts_columns_not_bytes() {
+        assert_eq!(text_width("日本語 ok"), 9);
+        assert_eq!(wrap("日本 日本 日本", 10, 0), "日本 日本\n日本");
+        // Styles take no room and stay with their word
+        assert_eq!(
+            wrap("\x1b[1mbold\x1b[0m word", 6, 0),
+            "\x1b[1mbold\x1b[0m\nword"
+        );
+    }
+
+    #[test]
+    fn streamed_text_wraps_as_if_whole() {
+        let text = "Streaming text arrives in pieces of any size, 日本語 too";
+        let mut wrapper = Wrapper::new(12, 3);
+        let mut streamed: String = text.chars().map(|c| wrapper.push(&c.to_string())).collect();
+        streamed.push_str(&wrapper.finish());
+        assert_eq!(streamed, wrap(text, 12, 3));
+    }
+}